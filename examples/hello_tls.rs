@@ -25,6 +25,15 @@ fn main() -> std::io::Result<()> {
             )
             .await?;
         } else {
+            #[cfg(feature = "self-signed")]
+            app.listen(
+                TlsListener::build()
+                    .addrs("localhost:4433")
+                    .self_signed(tide_rustls::SelfSignedCert::localhost().persist("./tls")),
+            )
+            .await?;
+
+            #[cfg(not(feature = "self-signed"))]
             eprintln!(
                 "
 To run this app locally:
@@ -32,6 +41,10 @@ To run this app locally:
 * $ mkcert -key-file key.pem -cert-file cert.pem localhost 127.0.0.1 ::1
 * $ env TIDE_CERT=cert.pem TIDE_KEY=key.pem cargo run --example hello_tls
 * $ curl -v https://localhost:4433/secure
+
+Or, to serve a generated self-signed certificate from ./tls:
+* $ cargo run --example hello_tls --features self-signed
+* $ curl -v --cacert tls/localhost+127.0.0.1+__1.cert.pem https://localhost:4433/secure
 "
             );
         }
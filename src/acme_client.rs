@@ -0,0 +1,798 @@
+//! Just enough of the ACME protocol (RFC 8555) to order a certificate
+//! using TLS-ALPN-01 challenges (RFC 8737).
+
+use crate::certificates::load_certs;
+
+use tide::http::headers::{CONTENT_TYPE, LOCATION};
+use tide::http::{Method, Request, Url};
+
+use async_rustls::TlsConnector;
+use async_std::net::TcpStream;
+use async_std::{future, io, task};
+
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::ClientConfig;
+use serde_json::{json, Value};
+use webpki::DNSNameRef;
+
+use std::fmt::{self, Debug, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 60;
+
+/// An ACME account, registered with a particular directory.
+pub(crate) struct AcmeClient {
+    connector: TlsConnector,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    new_nonce: String,
+    new_order: String,
+    account: Option<String>,
+    nonce: Option<String>,
+}
+
+impl Debug for AcmeClient {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcmeClient")
+            .field("account", &self.account)
+            .finish()
+    }
+}
+
+/// An order for a certificate, which is ready to be finalized once
+/// each of its authorizations is valid.
+#[derive(Debug)]
+pub(crate) struct Order {
+    url: String,
+    pub(crate) authorizations: Vec<String>,
+    finalize: String,
+}
+
+/// A TLS-ALPN-01 challenge that must be answered to prove control of
+/// `domain`.
+#[derive(Debug)]
+pub(crate) struct Challenge {
+    pub(crate) domain: String,
+    pub(crate) url: String,
+    pub(crate) key_authorization: String,
+}
+
+/// Generates a new pkcs8-encoded account key.
+pub(crate) fn generate_account_key() -> io::Result<Vec<u8>> {
+    EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+        .map(|pkcs8| pkcs8.as_ref().to_vec())
+        .map_err(|_| io::Error::other("unable to generate acme account key"))
+}
+
+impl AcmeClient {
+    /// Fetches the directory at `directory_url` and registers the
+    /// account for `account_key`, or finds the existing account if it
+    /// has been registered before.
+    pub(crate) async fn new(
+        directory_url: &str,
+        directory_ca: Option<&Path>,
+        account_key: &[u8],
+        contact: &[String],
+    ) -> io::Result<Self> {
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid acme account key"))?;
+
+        let mut config = ClientConfig::new();
+        config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        if let Some(directory_ca) = directory_ca {
+            for cert in load_certs(directory_ca)? {
+                config.root_store.add(&cert).map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid certificate in {}: {}", directory_ca.display(), err),
+                    )
+                })?;
+            }
+        }
+
+        let mut client = Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            key,
+            rng: SystemRandom::new(),
+            new_nonce: String::new(),
+            new_order: String::new(),
+            account: None,
+            nonce: None,
+        };
+
+        let directory = client.get(directory_url).await?.json()?;
+        client.new_nonce = string(&directory["newNonce"])?;
+        client.new_order = string(&directory["newOrder"])?;
+
+        let new_account = string(&directory["newAccount"])?;
+        let payload = json!({ "termsOfServiceAgreed": true, "contact": contact });
+        let account = client.post(&new_account, Some(&payload)).await?;
+        client.account = Some(account.location.ok_or_else(|| missing("account url"))?);
+
+        Ok(client)
+    }
+
+    pub(crate) async fn new_order(&mut self, domains: &[String]) -> io::Result<Order> {
+        let identifiers: Vec<Value> = domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect();
+
+        let new_order = self.new_order.clone();
+        let reply = self
+            .post(&new_order, Some(&json!({ "identifiers": identifiers })))
+            .await?;
+        let order = reply.json()?;
+
+        Ok(Order {
+            url: reply.location.ok_or_else(|| missing("order url"))?,
+            authorizations: order["authorizations"]
+                .as_array()
+                .ok_or_else(|| missing("authorizations"))?
+                .iter()
+                .map(string)
+                .collect::<io::Result<_>>()?,
+            finalize: string(&order["finalize"])?,
+        })
+    }
+
+    /// Returns the TLS-ALPN-01 challenge for an authorization, or
+    /// `None` if the authorization is already valid, as it is when
+    /// the account has proven control of the domain recently.
+    pub(crate) async fn tls_alpn_challenge(
+        &mut self,
+        authorization: &str,
+    ) -> io::Result<Option<Challenge>> {
+        let authorization = self.post(authorization, None).await?.json()?;
+        if authorization["status"] == "valid" {
+            return Ok(None);
+        }
+
+        let domain = string(&authorization["identifier"]["value"])?;
+        let challenge = authorization["challenges"]
+            .as_array()
+            .and_then(|challenges| {
+                challenges
+                    .iter()
+                    .find(|challenge| challenge["type"] == "tls-alpn-01")
+            })
+            .ok_or_else(|| {
+                io::Error::other(format!("no tls-alpn-01 challenge offered for {}", domain))
+            })?;
+
+        Ok(Some(Challenge {
+            domain,
+            url: string(&challenge["url"])?,
+            key_authorization: format!("{}.{}", string(&challenge["token"])?, self.thumbprint()),
+        }))
+    }
+
+    /// Tells the certificate authority that a challenge is ready to be
+    /// validated, and waits until it has been.
+    pub(crate) async fn complete_challenge(
+        &mut self,
+        challenge: &Challenge,
+        authorization: &str,
+    ) -> io::Result<()> {
+        self.post(&challenge.url, Some(&json!({}))).await?;
+        self.poll(authorization).await.map(|_| ())
+    }
+
+    /// Submits a certificate signing request for a ready order, and
+    /// returns the issued certificate chain in pem form.
+    pub(crate) async fn finalize(&mut self, order: &Order, csr: &[u8]) -> io::Result<String> {
+        let payload = json!({ "csr": base64url(csr) });
+        self.post(&order.finalize, Some(&payload)).await?;
+
+        let order = self.poll(&order.url).await?;
+        let certificate = string(&order["certificate"])?;
+        Ok(self.post(&certificate, None).await?.body)
+    }
+
+    /// Fetches an authorization or order until it is no longer in
+    /// progress.
+    async fn poll(&mut self, url: &str) -> io::Result<Value> {
+        for _ in 0..POLL_ATTEMPTS {
+            let object = self.post(url, None).await?.json()?;
+            match object["status"].as_str() {
+                Some("valid") => return Ok(object),
+                Some("pending") | Some("ready") | Some("processing") => {
+                    task::sleep(POLL_INTERVAL).await
+                }
+                status => {
+                    return Err(io::Error::other(format!(
+                        "acme {} is {}: {}",
+                        url,
+                        status.unwrap_or("unknown"),
+                        problem_detail(&object)
+                    )))
+                }
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("acme {} did not complete", url),
+        ))
+    }
+
+    async fn get(&mut self, url: &str) -> io::Result<Reply> {
+        let reply = self
+            .send(Request::new(Method::Get, parse_url(url)?))
+            .await?;
+        match reply.error {
+            Some(error) => Err(io::Error::other(format!(
+                "acme error from {}: {}",
+                url, error
+            ))),
+            None => Ok(reply),
+        }
+    }
+
+    /// Sends a JWS-signed POST request, or a POST-as-GET request when
+    /// there is no payload, retrying once if the nonce was rejected.
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> io::Result<Reply> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.fresh_nonce().await?,
+            };
+
+            let protected = match &self.account {
+                Some(account) => {
+                    json!({ "alg": "ES256", "kid": account, "nonce": nonce, "url": url })
+                }
+                None => json!({ "alg": "ES256", "jwk": self.jwk(), "nonce": nonce, "url": url }),
+            };
+            let protected = base64url(protected.to_string());
+            let payload =
+                payload.map_or_else(String::new, |payload| base64url(payload.to_string()));
+            let signature = self
+                .key
+                .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+                .map_err(|_| io::Error::other("unable to sign acme request"))?;
+
+            let body = json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": base64url(signature),
+            });
+
+            let mut req = Request::new(Method::Post, parse_url(url)?);
+            req.set_body(body.to_string());
+            req.insert_header(CONTENT_TYPE, "application/jose+json");
+
+            let reply = self.send(req).await?;
+            match reply.error {
+                None => return Ok(reply),
+                Some(error)
+                    if error.starts_with("urn:ietf:params:acme:error:badNonce") && !retried =>
+                {
+                    retried = true;
+                }
+                Some(error) => {
+                    return Err(io::Error::other(format!(
+                        "acme error from {}: {}",
+                        url, error
+                    )))
+                }
+            }
+        }
+    }
+
+    async fn fresh_nonce(&mut self) -> io::Result<String> {
+        let url = parse_url(&self.new_nonce)?;
+        self.send(Request::new(Method::Head, url)).await?;
+        self.nonce.take().ok_or_else(|| missing("replay-nonce"))
+    }
+
+    async fn send(&mut self, req: Request) -> io::Result<Reply> {
+        let url = req.url().clone();
+        let host = url.host_str().ok_or_else(|| missing("host"))?.to_string();
+        let port = url.port_or_known_default().ok_or_else(|| missing("port"))?;
+
+        let response = future::timeout(REQUEST_TIMEOUT, async {
+            let stream = TcpStream::connect((host.as_str(), port)).await?;
+            let response = match url.scheme() {
+                "https" => {
+                    let domain = DNSNameRef::try_from_ascii_str(&host).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "invalid acme hostname")
+                    })?;
+                    let stream = self.connector.connect(domain, stream).await?;
+                    async_h1::connect(stream, req).await
+                }
+                "http" => async_h1::connect(stream, req).await,
+                scheme => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unsupported acme url scheme {}", scheme),
+                    ))
+                }
+            };
+
+            let mut response = response.map_err(|error| io::Error::other(error.to_string()))?;
+            let body = response
+                .body_string()
+                .await
+                .map_err(|error| io::Error::other(error.to_string()))?;
+            Ok((response, body))
+        })
+        .await
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("acme request to {} timed out", url),
+            )
+        })??;
+
+        let (response, body) = response;
+        if let Some(nonce) = response.header("replay-nonce") {
+            self.nonce = Some(nonce.last().to_string());
+        }
+
+        let error = if response.status().is_success() {
+            None
+        } else {
+            let problem = serde_json::from_str(&body).unwrap_or(Value::Null);
+            Some(match problem["type"].as_str() {
+                Some(kind) => format!("{}: {}", kind, problem_detail(&problem)),
+                None => format!("{} {}", response.status(), body),
+            })
+        };
+
+        Ok(Reply {
+            location: response
+                .header(LOCATION)
+                .map(|location| location.last().to_string()),
+            body,
+            error,
+        })
+    }
+
+    fn jwk(&self) -> Value {
+        let (x, y) = self.public_key_coordinates();
+        json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y })
+    }
+
+    /// The RFC 7638 thumbprint of the account key, which requires the
+    /// members in lexicographic order without whitespace.
+    fn thumbprint(&self) -> String {
+        let (x, y) = self.public_key_coordinates();
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y);
+        base64url(digest(&SHA256, jwk.as_bytes()))
+    }
+
+    /// The public key is an uncompressed point: a 4 followed by the x
+    /// and y coordinates.
+    fn public_key_coordinates(&self) -> (String, String) {
+        let point = self.key.public_key().as_ref();
+        (base64url(&point[1..33]), base64url(&point[33..65]))
+    }
+}
+
+#[derive(Debug)]
+struct Reply {
+    location: Option<String>,
+    body: String,
+    error: Option<String>,
+}
+
+impl Reply {
+    fn json(&self) -> io::Result<Value> {
+        serde_json::from_str(&self.body)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Finds the most specific explanation of a failure in a problem
+/// document, or in an authorization whose challenge failed.
+fn problem_detail(object: &Value) -> String {
+    let challenge_error = object["challenges"]
+        .as_array()
+        .and_then(|challenges| challenges.iter().find(|c| c["error"].is_object()))
+        .map(|challenge| &challenge["error"]);
+
+    let problem = challenge_error.unwrap_or(if object["error"].is_object() {
+        &object["error"]
+    } else {
+        object
+    });
+
+    problem["detail"]
+        .as_str()
+        .map_or_else(|| object.to_string(), String::from)
+}
+
+fn base64url(bytes: impl AsRef<[u8]>) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn string(value: &Value) -> io::Result<String> {
+    value
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| missing(&value.to_string()))
+}
+
+fn parse_url(url: &str) -> io::Result<Url> {
+    Url::parse(url).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+fn missing(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("acme response is missing {}", what),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::net::TcpListener;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+    use tide::http::{Response, StatusCode};
+
+    use std::sync::Mutex;
+
+    /// A certificate authority's directory, served over plain http,
+    /// that checks each request the way a real one would and records
+    /// what it was sent.
+    #[derive(Default)]
+    struct Directory {
+        base: String,
+        authorization_valid: bool,
+        challenge_fails: bool,
+        reject_nonce_once: bool,
+        account_key: Option<Vec<u8>>,
+        nonces: Vec<String>,
+        challenged: bool,
+        requests: Vec<(String, Value)>,
+    }
+
+    async fn serve(directory: Directory) -> (String, Arc<Mutex<Directory>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let directory = Arc::new(Mutex::new(Directory {
+            base: base.clone(),
+            ..directory
+        }));
+
+        let shared = directory.clone();
+        task::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let directory = shared.clone();
+                task::spawn(async_h1::accept(stream, move |mut req| {
+                    let directory = directory.clone();
+                    async move {
+                        let body = req.body_string().await?;
+                        let mut directory = directory.lock().unwrap();
+                        Ok(directory.respond(req.method(), req.url().path(), &body))
+                    }
+                }));
+            }
+        });
+
+        (format!("{}/directory", base), directory)
+    }
+
+    impl Directory {
+        fn respond(&mut self, method: Method, path: &str, body: &str) -> Response {
+            let nonce = format!("nonce-{}", self.nonces.len());
+            self.nonces.push(nonce.clone());
+
+            let mut res = match method {
+                Method::Get if path == "/directory" => json_response(
+                    StatusCode::Ok,
+                    json!({
+                        "newNonce": self.url("/new-nonce"),
+                        "newAccount": self.url("/new-account"),
+                        "newOrder": self.url("/new-order"),
+                    }),
+                ),
+                Method::Head if path == "/new-nonce" => Response::new(StatusCode::Ok),
+                Method::Post => self.post(path, body),
+                _ => Response::new(StatusCode::NotFound),
+            };
+            res.insert_header("replay-nonce", nonce);
+            res
+        }
+
+        fn post(&mut self, path: &str, body: &str) -> Response {
+            let jws: Value = serde_json::from_str(body).unwrap();
+            let protected = decode_json(&jws["protected"]);
+            assert_eq!(protected["alg"], "ES256");
+            assert_eq!(protected["url"], self.url(path));
+
+            if path == "/new-account" {
+                let jwk = &protected["jwk"];
+                assert_eq!((&jwk["kty"], &jwk["crv"]), (&json!("EC"), &json!("P-256")));
+                let point = [&[4][..], &decode(&jwk["x"]), &decode(&jwk["y"])].concat();
+                self.account_key = Some(point);
+            } else {
+                assert_eq!(protected["kid"], self.url("/account/1"));
+                assert!(protected.get("jwk").is_none());
+            }
+
+            let signed = format!(
+                "{}.{}",
+                jws["protected"].as_str().unwrap(),
+                jws["payload"].as_str().unwrap()
+            );
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, self.account_key.as_ref().unwrap())
+                .verify(signed.as_bytes(), &decode(&jws["signature"]))
+                .expect("invalid jws signature");
+
+            // each nonce may only be used once, and the directory may
+            // reject any of them
+            let nonce = protected["nonce"].as_str().unwrap();
+            let position = self.nonces.iter().position(|issued| issued == nonce);
+            if std::mem::take(&mut self.reject_nonce_once) || position.is_none() {
+                return json_response(
+                    StatusCode::BadRequest,
+                    json!({
+                        "type": "urn:ietf:params:acme:error:badNonce",
+                        "detail": "bad nonce",
+                    }),
+                );
+            }
+            self.nonces[position.unwrap()].clear();
+
+            let payload = match jws["payload"].as_str().unwrap() {
+                "" => Value::Null,
+                _ => decode_json(&jws["payload"]),
+            };
+            self.requests.push((path.to_string(), payload.clone()));
+
+            match path {
+                "/new-account" => {
+                    assert_eq!(payload["termsOfServiceAgreed"], true);
+                    let mut res = json_response(StatusCode::Created, json!({ "status": "valid" }));
+                    res.insert_header(LOCATION, self.url("/account/1"));
+                    res
+                }
+
+                "/new-order" => {
+                    let mut res = json_response(
+                        StatusCode::Created,
+                        json!({
+                            "status": "pending",
+                            "authorizations": [self.url("/authz/1")],
+                            "finalize": self.url("/finalize/1"),
+                        }),
+                    );
+                    res.insert_header(LOCATION, self.url("/order/1"));
+                    res
+                }
+
+                "/authz/1" => {
+                    let (status, error) = match (self.authorization_valid, self.challenged) {
+                        (true, _) => ("valid", Value::Null),
+                        (false, false) => ("pending", Value::Null),
+                        (false, true) if self.challenge_fails => (
+                            "invalid",
+                            json!({ "type": "urn:ietf:params:acme:error:connection", "detail": "connection refused" }),
+                        ),
+                        (false, true) => ("valid", Value::Null),
+                    };
+                    json_response(
+                        StatusCode::Ok,
+                        json!({
+                            "status": status,
+                            "identifier": { "type": "dns", "value": "example.com" },
+                            "challenges": [
+                                { "type": "http-01", "url": self.url("/chall/0"), "token": "http" },
+                                { "type": "tls-alpn-01", "url": self.url("/chall/1"), "token": "tls", "error": error },
+                            ],
+                        }),
+                    )
+                }
+
+                "/chall/1" => {
+                    assert_eq!(payload, json!({}));
+                    self.challenged = true;
+                    json_response(StatusCode::Ok, json!({ "status": "processing" }))
+                }
+
+                "/finalize/1" => json_response(StatusCode::Ok, json!({ "status": "processing" })),
+
+                "/order/1" => json_response(
+                    StatusCode::Ok,
+                    json!({ "status": "valid", "certificate": self.url("/cert/1") }),
+                ),
+
+                "/cert/1" => {
+                    let mut res = Response::new(StatusCode::Ok);
+                    res.set_body("issued chain");
+                    res
+                }
+
+                _ => Response::new(StatusCode::NotFound),
+            }
+        }
+
+        fn url(&self, path: &str) -> String {
+            format!("{}{}", self.base, path)
+        }
+
+        fn paths(&self) -> Vec<&str> {
+            self.requests
+                .iter()
+                .map(|(path, _)| path.as_str())
+                .collect()
+        }
+    }
+
+    fn json_response(status: StatusCode, body: Value) -> Response {
+        let mut res = Response::new(status);
+        res.set_body(body.to_string());
+        res
+    }
+
+    fn decode(value: &Value) -> Vec<u8> {
+        base64::decode_config(value.as_str().unwrap(), base64::URL_SAFE_NO_PAD).unwrap()
+    }
+
+    fn decode_json(value: &Value) -> Value {
+        serde_json::from_slice(&decode(value)).unwrap()
+    }
+
+    async fn client(directory_url: &str) -> io::Result<AcmeClient> {
+        let account_key = generate_account_key()?;
+        AcmeClient::new(
+            directory_url,
+            None,
+            &account_key,
+            &[String::from("mailto:admin@example.com")],
+        )
+        .await
+    }
+
+    #[test]
+    fn jwk_and_thumbprint() {
+        task::block_on(async {
+            let (url, _) = serve(Directory::default()).await;
+            let client = client(&url).await.unwrap();
+
+            let jwk = client.jwk();
+            let point = client.key.public_key().as_ref();
+            assert_eq!(point[0], 4);
+            assert_eq!(decode(&jwk["x"]), &point[1..33]);
+            assert_eq!(decode(&jwk["y"]), &point[33..]);
+
+            // serde_json sorts object members and adds no whitespace,
+            // which is the form that RFC 7638 hashes
+            let canonical = jwk.to_string();
+            assert!(canonical.starts_with(r#"{"crv":"P-256","kty":"EC","x":""#));
+            assert_eq!(
+                client.thumbprint(),
+                base64url(digest(&SHA256, canonical.as_bytes()))
+            );
+        });
+    }
+
+    #[test]
+    fn order() {
+        task::block_on(async {
+            let (url, directory) = serve(Directory::default()).await;
+            let mut client = client(&url).await.unwrap();
+            assert_eq!(
+                client.account.as_deref(),
+                Some(&*directory.lock().unwrap().url("/account/1"))
+            );
+
+            let order = client
+                .new_order(&[String::from("example.com")])
+                .await
+                .unwrap();
+            let challenge = client
+                .tls_alpn_challenge(&order.authorizations[0])
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(challenge.domain, "example.com");
+            assert_eq!(
+                challenge.key_authorization,
+                format!("tls.{}", client.thumbprint())
+            );
+
+            client
+                .complete_challenge(&challenge, &order.authorizations[0])
+                .await
+                .unwrap();
+            let chain = client.finalize(&order, b"csr").await.unwrap();
+            assert_eq!(chain, "issued chain");
+
+            let directory = directory.lock().unwrap();
+            assert_eq!(
+                directory.paths(),
+                [
+                    "/new-account",
+                    "/new-order",
+                    "/authz/1",
+                    "/chall/1",
+                    "/authz/1",
+                    "/finalize/1",
+                    "/order/1",
+                    "/cert/1",
+                ]
+            );
+            assert_eq!(
+                directory.requests[1].1["identifiers"],
+                json!([{ "type": "dns", "value": "example.com" }])
+            );
+            assert_eq!(directory.requests[5].1["csr"], base64url(b"csr"));
+        });
+    }
+
+    #[test]
+    fn authorization_already_valid() {
+        task::block_on(async {
+            let (url, _) = serve(Directory {
+                authorization_valid: true,
+                ..Directory::default()
+            })
+            .await;
+            let mut client = client(&url).await.unwrap();
+            let order = client
+                .new_order(&[String::from("example.com")])
+                .await
+                .unwrap();
+            assert!(client
+                .tls_alpn_challenge(&order.authorizations[0])
+                .await
+                .unwrap()
+                .is_none());
+        });
+    }
+
+    #[test]
+    fn failed_challenge() {
+        task::block_on(async {
+            let (url, _) = serve(Directory {
+                challenge_fails: true,
+                ..Directory::default()
+            })
+            .await;
+            let mut client = client(&url).await.unwrap();
+            let order = client
+                .new_order(&[String::from("example.com")])
+                .await
+                .unwrap();
+            let authorization = &order.authorizations[0];
+            let challenge = client.tls_alpn_challenge(authorization).await.unwrap();
+
+            let error = client
+                .complete_challenge(&challenge.unwrap(), authorization)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("acme {} is invalid: connection refused", authorization)
+            );
+        });
+    }
+
+    #[test]
+    fn rejected_nonce_is_retried() {
+        task::block_on(async {
+            let (url, directory) = serve(Directory {
+                reject_nonce_once: true,
+                ..Directory::default()
+            })
+            .await;
+            client(&url).await.unwrap();
+            assert_eq!(directory.lock().unwrap().paths(), ["/new-account"]);
+        });
+    }
+}
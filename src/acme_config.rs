@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Settings for obtaining and renewing a certificate from an
+/// [ACME](https://datatracker.ietf.org/doc/html/rfc8555) certificate
+/// authority such as Let's Encrypt, for use with
+/// [`TlsListenerBuilder::acme`](crate::TlsListenerBuilder::acme).
+///
+/// Ownership of each domain is proven with the TLS-ALPN-01 challenge,
+/// which the certificate authority performs by connecting to port 443
+/// of the domain. The listener answers these challenges itself, so it
+/// must be reachable on port 443 for every domain, and the challenge
+/// connections never reach tide.
+///
+/// The account key, certificate and certificate key are kept in the
+/// cache directory so that restarts do not request a new certificate.
+/// The certificate is renewed in the background before it expires.
+/// Using this agrees to the terms of service of the certificate
+/// authority.
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::{AcmeConfig, TlsListener};
+/// let listener = TlsListener::<()>::build()
+///     .addrs("0.0.0.0:443")
+///     .acme(
+///         AcmeConfig::new(vec!["example.com", "www.example.com"], "./acme-cache")
+///             .contact("mailto:admin@example.com")
+///             .directory(AcmeConfig::LETS_ENCRYPT_STAGING),
+///     )
+///     .finish();
+/// ```
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    pub(crate) domains: Vec<String>,
+    pub(crate) cache_dir: PathBuf,
+    pub(crate) directory: String,
+    pub(crate) directory_ca: Option<PathBuf>,
+    pub(crate) contact: Vec<String>,
+    pub(crate) renew_before: Duration,
+}
+
+impl AcmeConfig {
+    /// The directory url for Let's Encrypt, which is the default.
+    pub const LETS_ENCRYPT_PRODUCTION: &'static str =
+        "https://acme-v02.api.letsencrypt.org/directory";
+
+    /// The directory url for the Let's Encrypt staging environment,
+    /// which has much higher rate limits but issues certificates that
+    /// browsers do not trust.
+    pub const LETS_ENCRYPT_STAGING: &'static str =
+        "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+    /// Obtain a single certificate covering all of `domains`, keeping
+    /// the account key and certificate in `cache_dir`, which is
+    /// created if it does not exist.
+    pub fn new(
+        domains: impl IntoIterator<Item = impl Into<String>>,
+        cache_dir: impl AsRef<Path>,
+    ) -> Self {
+        Self {
+            domains: domains
+                .into_iter()
+                .map(|domain| domain.into().to_ascii_lowercase())
+                .collect(),
+            cache_dir: cache_dir.as_ref().into(),
+            directory: Self::LETS_ENCRYPT_PRODUCTION.into(),
+            directory_ca: None,
+            contact: Vec::new(),
+            renew_before: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+
+    /// Use the ACME directory at this url instead of Let's Encrypt.
+    pub fn directory(mut self, url: impl Into<String>) -> Self {
+        self.directory = url.into();
+        self
+    }
+
+    /// Trust the certificates in this pem-encoded CA bundle file when
+    /// connecting to the ACME directory, in addition to the usual web
+    /// roots. This is needed for test servers such as
+    /// [Pebble](https://github.com/letsencrypt/pebble).
+    pub fn directory_ca(mut self, path: impl AsRef<Path>) -> Self {
+        self.directory_ca = Some(path.as_ref().into());
+        self
+    }
+
+    /// Provide a contact url for the ACME account, such as
+    /// `mailto:admin@example.com`, which the certificate authority may
+    /// use to warn of problems. This can be called more than once.
+    pub fn contact(mut self, contact: impl Into<String>) -> Self {
+        self.contact.push(contact.into());
+        self
+    }
+
+    /// Renew the certificate once it is within this long of expiring.
+    /// Defaults to 30 days.
+    pub fn renew_before(mut self, renew_before: Duration) -> Self {
+        self.renew_before = renew_before;
+        self
+    }
+}
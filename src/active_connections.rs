@@ -0,0 +1,70 @@
+use async_lock::SemaphoreGuardArc;
+use async_std::channel::Sender;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// What a [`TlsListener`](crate::TlsListener) does with new tcp
+/// connections once it has reached
+/// [`TlsListenerBuilder::max_connections`](crate::TlsListenerBuilder::max_connections).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionLimitBehavior {
+    /// Stop accepting from the tcp listener until a connection
+    /// closes, leaving new connections in the operating system's
+    /// listen backlog. This is the default.
+    #[default]
+    Backpressure,
+
+    /// Keep accepting, but close new connections immediately without
+    /// attempting a TLS handshake.
+    Close,
+}
+
+/// A handle to the number of connections a
+/// [`TlsListener`](crate::TlsListener) is currently serving,
+/// including those still performing a TLS handshake. This is
+/// obtained with
+/// [`TlsListener::active_connections`](crate::TlsListener::active_connections).
+#[derive(Debug, Clone, Default)]
+pub struct ActiveConnections(Arc<AtomicUsize>);
+
+impl ActiveConnections {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of currently open connections.
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Counts a new connection until the returned guard is dropped.
+    pub(crate) fn track(
+        &self,
+        drain: Sender<()>,
+        permit: Option<SemaphoreGuardArc>,
+    ) -> ConnectionGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard {
+            active: self.clone(),
+            _drain: drain,
+            _permit: permit,
+        }
+    }
+}
+
+/// Held for the lifetime of each connection task. Dropping it
+/// releases the connection's slot under `max_connections` and lets a
+/// draining listener know the connection has closed.
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard {
+    active: ActiveConnections,
+    _drain: Sender<()>,
+    _permit: Option<SemaphoreGuardArc>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.active.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::der;
+
+use async_std::task;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How long before a certificate expires to log warnings about it,
+/// unless [`TlsListenerBuilder::cert_expiry_warnings`] says otherwise.
+///
+/// [`TlsListenerBuilder::cert_expiry_warnings`]: crate::TlsListenerBuilder::cert_expiry_warnings
+pub(crate) const DEFAULT_WARNINGS: [Duration; 3] = [
+    Duration::from_secs(30 * 24 * 60 * 60),
+    Duration::from_secs(7 * 24 * 60 * 60),
+    Duration::from_secs(24 * 60 * 60),
+];
+
+/// How often to compare each certificate's expiry with the warning
+/// thresholds.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A handle to the expiry dates of the certificates a
+/// [`TlsListener`](crate::TlsListener) serves, for reporting from a
+/// health check. This is obtained with
+/// [`TlsListener::cert_expiry`](crate::TlsListener::cert_expiry). It
+/// is empty until the listener is bound, and stays empty for
+/// listeners configured with
+/// [`TlsListenerBuilder::config`](crate::TlsListenerBuilder::config)
+/// or
+/// [`TlsListenerBuilder::tls_acceptor`](crate::TlsListenerBuilder::tls_acceptor),
+/// whose certificates are not known to the listener. Reloaded and
+/// renewed certificates replace the ones they were loaded in place of.
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
+/// # fn main() -> tide::Result<()> { async_std::task::block_on(async {
+/// let mut app = tide::new();
+///
+/// let listener = TlsListener::build()
+///     .addrs("localhost:4433")
+///     .cert("./tls/localhost-4433.cert")
+///     .key("./tls/localhost-4433.key")
+///     .finish()?;
+///
+/// let cert_expiry = listener.cert_expiry();
+/// app.at("/health").get(move |_| {
+///     let expires_in = cert_expiry.expires_in();
+///     async move {
+///         Ok(match expires_in {
+///             Some(expires_in) => format!("cert expires in {}s", expires_in.as_secs()),
+///             None => String::from("no cert loaded"),
+///         })
+///     }
+/// });
+///
+/// # if false {
+/// app.listen(listener).await?;
+/// # }
+/// # Ok(()) }) }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CertExpiry(Arc<RwLock<BTreeMap<String, Expiry>>>);
+
+#[derive(Debug)]
+struct Expiry {
+    not_after: SystemTime,
+    /// The smallest threshold that has been warned about, which is
+    /// zero once the certificate has expired.
+    warned: Option<Duration>,
+}
+
+impl CertExpiry {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The earliest time at which one of the certificates expires.
+    pub fn not_after(&self) -> Option<SystemTime> {
+        let certs = self.0.read().unwrap();
+        certs.values().map(|expiry| expiry.not_after).min()
+    }
+
+    /// How long until one of the certificates expires, which is zero
+    /// once one of them has.
+    pub fn expires_in(&self) -> Option<Duration> {
+        self.not_after().map(|not_after| {
+            not_after
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+        })
+    }
+
+    /// The expiry of each certificate, by the name that warnings refer
+    /// to it by: its sni hostname, the acme or self-signed
+    /// certificate's names, or the path it was loaded from.
+    pub fn certs(&self) -> Vec<(String, SystemTime)> {
+        let certs = self.0.read().unwrap();
+        certs
+            .iter()
+            .map(|(name, expiry)| (name.clone(), expiry.not_after))
+            .collect()
+    }
+
+    /// Records the expiry of a certificate that has been loaded,
+    /// replacing the one it was loaded in place of.
+    pub(crate) fn update(&self, name: &str, not_after: SystemTime) {
+        let mut certs = self.0.write().unwrap();
+        if certs.get(name).map(|expiry| expiry.not_after) == Some(not_after) {
+            return;
+        }
+
+        certs.insert(
+            name.to_string(),
+            Expiry {
+                not_after,
+                warned: None,
+            },
+        );
+    }
+
+    /// Logs a warning as each certificate passes each of the
+    /// `thresholds`, and an error once it has expired, checking right
+    /// away and then periodically. This never returns.
+    pub(crate) async fn monitor(&self, thresholds: Vec<Duration>) {
+        loop {
+            self.check(&thresholds, SystemTime::now());
+            task::sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    fn check(&self, thresholds: &[Duration], now: SystemTime) {
+        let mut certs = self.0.write().unwrap();
+        for (name, expiry) in certs.iter_mut() {
+            let expires_in = match expiry.not_after.duration_since(now) {
+                Ok(expires_in) => expires_in,
+                Err(_) => {
+                    if expiry.warned != Some(Duration::ZERO) {
+                        tide::log::error!("tls certificate has expired", {
+                            cert: name,
+                            expired_at: der::format_time(expiry.not_after),
+                        });
+                        expiry.warned = Some(Duration::ZERO);
+                    }
+                    continue;
+                }
+            };
+
+            let threshold = thresholds
+                .iter()
+                .filter(|threshold| expires_in <= **threshold)
+                .min();
+
+            if let Some(&threshold) = threshold {
+                if expiry.warned.map_or(true, |warned| threshold < warned) {
+                    tide::log::warn!("tls certificate expires soon", {
+                        cert: name,
+                        expires_at: der::format_time(expiry.not_after),
+                        expires_in_days: expires_in.as_secs() / (24 * 60 * 60),
+                    });
+                    expiry.warned = Some(threshold);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn warned(cert_expiry: &CertExpiry, name: &str) -> Option<Duration> {
+        cert_expiry.0.read().unwrap()[name].warned
+    }
+
+    #[test]
+    fn warns_once_per_threshold_then_errors_once_expired() {
+        let not_after = SystemTime::UNIX_EPOCH + 1000 * DAY;
+        let cert_expiry = CertExpiry::new();
+        cert_expiry.update("example.com", not_after);
+
+        let checks = [
+            (40 * DAY, None),
+            (30 * DAY, Some(30 * DAY)),
+            (29 * DAY, Some(30 * DAY)),
+            (8 * DAY, Some(30 * DAY)),
+            (7 * DAY, Some(7 * DAY)),
+            (2 * DAY, Some(7 * DAY)),
+            (DAY / 2, Some(DAY)),
+            (Duration::from_secs(1), Some(DAY)),
+        ];
+        for (before_expiry, expected) in checks {
+            cert_expiry.check(&DEFAULT_WARNINGS, not_after - before_expiry);
+            assert_eq!(
+                warned(&cert_expiry, "example.com"),
+                expected,
+                "{:?}",
+                before_expiry
+            );
+        }
+
+        for after_expiry in [Duration::from_secs(1), DAY, 10 * DAY] {
+            cert_expiry.check(&DEFAULT_WARNINGS, not_after + after_expiry);
+            assert_eq!(warned(&cert_expiry, "example.com"), Some(Duration::ZERO));
+        }
+    }
+
+    #[test]
+    fn skips_thresholds_already_passed() {
+        let not_after = SystemTime::UNIX_EPOCH + 1000 * DAY;
+        let cert_expiry = CertExpiry::new();
+        cert_expiry.update("example.com", not_after);
+
+        cert_expiry.check(&DEFAULT_WARNINGS, not_after - 3 * DAY);
+        assert_eq!(warned(&cert_expiry, "example.com"), Some(7 * DAY));
+
+        cert_expiry.check(&[], not_after - DAY / 2);
+        assert_eq!(warned(&cert_expiry, "example.com"), Some(7 * DAY));
+    }
+
+    #[test]
+    fn renewed_certs_are_warned_about_afresh() {
+        let not_after = SystemTime::UNIX_EPOCH + 1000 * DAY;
+        let cert_expiry = CertExpiry::new();
+        cert_expiry.update("example.com", not_after);
+        cert_expiry.update("example.org", not_after + 90 * DAY);
+
+        cert_expiry.check(&DEFAULT_WARNINGS, not_after + DAY);
+        assert_eq!(warned(&cert_expiry, "example.com"), Some(Duration::ZERO));
+        assert_eq!(warned(&cert_expiry, "example.org"), None);
+
+        // reloading the same cert keeps its warnings
+        cert_expiry.update("example.com", not_after);
+        assert_eq!(warned(&cert_expiry, "example.com"), Some(Duration::ZERO));
+
+        cert_expiry.update("example.com", not_after + 60 * DAY);
+        assert_eq!(warned(&cert_expiry, "example.com"), None);
+        cert_expiry.check(&DEFAULT_WARNINGS, not_after + 40 * DAY);
+        assert_eq!(warned(&cert_expiry, "example.com"), Some(30 * DAY));
+
+        assert_eq!(cert_expiry.not_after(), Some(not_after + 60 * DAY));
+        assert_eq!(
+            cert_expiry.certs(),
+            [
+                ("example.com".to_string(), not_after + 60 * DAY),
+                ("example.org".to_string(), not_after + 90 * DAY),
+            ]
+        );
+    }
+}
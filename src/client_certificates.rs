@@ -0,0 +1,52 @@
+use rustls::Certificate;
+
+use std::ops::Deref;
+
+/// The certificate chain presented by a client during the TLS
+/// handshake, starting with the client's own certificate.
+///
+/// When a client provides certificates that the configured client
+/// certificate verifier, such as
+/// [`rustls::AllowAnyAuthenticatedClient`], accepts, tide-rustls
+/// attaches them to every request on that connection as an
+/// extension. This will only be present if the listener was
+/// configured to request client certificates, such as with
+/// [`TlsListenerBuilder::client_auth_required`](crate::TlsListenerBuilder::client_auth_required).
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::ClientCertificates;
+/// async fn endpoint(req: tide::Request<()>) -> tide::Result {
+///     match req.ext::<ClientCertificates>().and_then(|certs| certs.leaf()) {
+///         Some(cert) => Ok(format!("client cert is {} bytes", cert.0.len()).into()),
+///         None => Ok(tide::Response::new(tide::StatusCode::Unauthorized)),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificates(Vec<Certificate>);
+
+impl ClientCertificates {
+    pub(crate) fn new(certs: Vec<Certificate>) -> Self {
+        Self(certs)
+    }
+
+    /// The client's own certificate, the first in the chain.
+    pub fn leaf(&self) -> Option<&Certificate> {
+        self.0.first()
+    }
+
+    /// Returns the underlying certificate chain.
+    pub fn into_inner(self) -> Vec<Certificate> {
+        self.0
+    }
+}
+
+impl Deref for ClientCertificates {
+    type Target = [Certificate];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
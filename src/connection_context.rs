@@ -0,0 +1,63 @@
+use crate::{ClientCertificates, TlsConnectionInfo};
+
+use tide::http::Request;
+
+use rustls::{ServerSession, Session};
+
+use std::net::SocketAddr;
+
+/// Everything known about a connection once its handshake has
+/// completed, which is applied to each request served on it
+/// regardless of the http version in use.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionContext {
+    local_addr: Option<SocketAddr>,
+    peer_addr: Option<SocketAddr>,
+    tls_connection_info: Option<TlsConnectionInfo>,
+    client_certificates: Option<ClientCertificates>,
+}
+
+impl ConnectionContext {
+    pub(crate) fn new(
+        local_addr: Option<SocketAddr>,
+        peer_addr: Option<SocketAddr>,
+        session: &ServerSession,
+    ) -> Self {
+        Self {
+            local_addr,
+            peer_addr,
+            tls_connection_info: Some(TlsConnectionInfo::new(session)),
+            client_certificates: session.get_peer_certificates().map(ClientCertificates::new),
+        }
+    }
+
+    /// For a connection served without TLS, as with
+    /// [`PlaintextHttp::Serve`](crate::PlaintextHttp::Serve).
+    pub(crate) fn plaintext(local_addr: Option<SocketAddr>, peer_addr: Option<SocketAddr>) -> Self {
+        Self {
+            local_addr,
+            peer_addr,
+            tls_connection_info: None,
+            client_certificates: None,
+        }
+    }
+
+    pub(crate) fn apply(&self, req: &mut Request) {
+        req.set_local_addr(self.local_addr);
+        req.set_peer_addr(self.peer_addr);
+
+        let tls_connection_info = match &self.tls_connection_info {
+            Some(tls_connection_info) => tls_connection_info,
+            None => return,
+        };
+
+        if req.url_mut().set_scheme("https").is_err() {
+            tide::log::error!("unable to set https scheme on url", { url: req.url().to_string() });
+        }
+
+        req.ext_mut().insert(tls_connection_info.clone());
+        if let Some(client_certificates) = &self.client_certificates {
+            req.ext_mut().insert(client_certificates.clone());
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::{ListenerMetrics, TlsConnectionInfo};
+
+use async_std::io;
+
+use rustls::TLSError;
+
+use std::fmt::{self, Debug, Formatter};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Receives an event at each stage of each connection a
+/// [`TlsListener`](crate::TlsListener) serves, for metrics or audit
+/// logging. This is provided with
+/// [`TlsListenerBuilder::connection_observer`](crate::TlsListenerBuilder::connection_observer),
+/// and is implemented for any `Fn(&ConnectionEvent)` closure.
+///
+/// Events are delivered on the task serving the connection, so this
+/// should return quickly, handing off any slow work such as network
+/// requests to another task.
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::{ConnectionEvent, ConnectionEventKind, TlsListener};
+/// let listener = TlsListener::<()>::build()
+///     .addrs("localhost:4433")
+///     .cert("./tls/localhost-4433.cert")
+///     .key("./tls/localhost-4433.key")
+///     .connection_observer(|event: &ConnectionEvent| {
+///         if let ConnectionEventKind::HandshakeFailed { reason, .. } = event.kind() {
+///             eprintln!("handshake from {:?} failed: {:?}", event.peer_addr(), reason);
+///         }
+///     })
+///     .finish();
+/// ```
+pub trait ConnectionObserver: Send + Sync + 'static {
+    /// Called as each [`ConnectionEvent`] happens.
+    fn on_event(&self, event: &ConnectionEvent);
+}
+
+impl<F> ConnectionObserver for F
+where
+    F: Fn(&ConnectionEvent) + Send + Sync + 'static,
+{
+    fn on_event(&self, event: &ConnectionEvent) {
+        self(event)
+    }
+}
+
+impl Debug for dyn ConnectionObserver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("ConnectionObserver")
+    }
+}
+
+/// Something that happened to a connection, as delivered to a
+/// [`ConnectionObserver`].
+#[derive(Debug, Clone)]
+pub struct ConnectionEvent {
+    id: u64,
+    peer_addr: Option<SocketAddr>,
+    kind: ConnectionEventKind,
+}
+
+impl ConnectionEvent {
+    /// A number that identifies the connection among all of those
+    /// accepted by this process, to tie its events together.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The address of the client, which is the one conveyed by the
+    /// PROXY protocol header once that has been read, when
+    /// [`TlsListenerBuilder::proxy_protocol`](crate::TlsListenerBuilder::proxy_protocol)
+    /// is used.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// What happened.
+    pub fn kind(&self) -> &ConnectionEventKind {
+        &self.kind
+    }
+}
+
+/// The kinds of [`ConnectionEvent`]. Every connection begins with
+/// [`ConnectionEventKind::Accepted`] and ends with
+/// [`ConnectionEventKind::Closed`], and more kinds of event may be
+/// added in future versions.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ConnectionEventKind {
+    /// A tcp connection was accepted.
+    Accepted,
+
+    /// The TLS handshake completed. Connections served as plaintext,
+    /// with [`PlaintextHttp::Serve`](crate::PlaintextHttp::Serve) or
+    /// [`PlaintextHttp::Redirect`](crate::PlaintextHttp::Redirect),
+    /// have no handshake.
+    HandshakeCompleted {
+        /// How long after the connection was accepted the handshake
+        /// completed, including reading any PROXY protocol header.
+        duration: Duration,
+        /// The parameters negotiated in the handshake.
+        info: TlsConnectionInfo,
+    },
+
+    /// The connection was closed before the TLS handshake completed.
+    HandshakeFailed {
+        /// How long after the connection was accepted the handshake
+        /// failed.
+        duration: Duration,
+        /// Why the handshake failed.
+        reason: HandshakeFailure,
+        /// The error that caused the failure.
+        error: String,
+    },
+
+    /// Serving http on the connection failed, which closes it.
+    HttpError {
+        /// The error from the http/1.1 or http/2 server.
+        error: String,
+    },
+
+    /// The connection was closed.
+    Closed {
+        /// How long the connection was open.
+        duration: Duration,
+        /// The bytes of application data received over TLS, which
+        /// are not counted for plaintext connections.
+        bytes_read: u64,
+        /// The bytes of application data sent over TLS, which are not
+        /// counted for plaintext connections.
+        bytes_written: u64,
+    },
+}
+
+/// Why a TLS handshake failed, as reported with
+/// [`ConnectionEventKind::HandshakeFailed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HandshakeFailure {
+    /// The handshake took longer than
+    /// [`TlsListenerBuilder::handshake_timeout`](crate::TlsListenerBuilder::handshake_timeout).
+    Timeout,
+
+    /// The PROXY protocol header was malformed or missing.
+    ProxyProtocol,
+
+    /// The client aborted the handshake with an alert.
+    AlertReceived,
+
+    /// The client and server have no protocol version, cipher suite or
+    /// ALPN protocol in common.
+    PeerIncompatible,
+
+    /// The client did not present a certificate that
+    /// [client authentication](crate::TlsListenerBuilder::client_auth_required)
+    /// accepts.
+    ClientCertificate,
+
+    /// The client violated the TLS protocol.
+    Protocol,
+
+    /// The connection failed at the tcp level, or was closed before
+    /// the handshake completed.
+    Io,
+}
+
+impl HandshakeFailure {
+    /// Categorizes an error from accepting a TLS connection.
+    pub(crate) fn from_error(error: &io::Error) -> Self {
+        let tls_error = error
+            .get_ref()
+            .and_then(|error| error.downcast_ref::<TLSError>());
+
+        match tls_error {
+            None => Self::Io,
+            Some(TLSError::AlertReceived(_)) => Self::AlertReceived,
+            Some(TLSError::PeerIncompatibleError(_)) | Some(TLSError::NoApplicationProtocol) => {
+                Self::PeerIncompatible
+            }
+            Some(TLSError::NoCertificatesPresented) | Some(TLSError::WebPKIError(_)) => {
+                Self::ClientCertificate
+            }
+            Some(_) => Self::Protocol,
+        }
+    }
+}
+
+/// Numbers connections for [`ConnectionEvent::id`].
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Records the events for one connection in the listener's metrics,
+/// and delivers them to the observer if there is one.
+#[derive(Debug)]
+pub(crate) struct ConnectionEvents {
+    metrics: ListenerMetrics,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    id: u64,
+    peer_addr: Option<SocketAddr>,
+    accepted_at: Instant,
+}
+
+impl ConnectionEvents {
+    pub(crate) fn new(
+        metrics: ListenerMetrics,
+        observer: Option<Arc<dyn ConnectionObserver>>,
+        peer_addr: Option<SocketAddr>,
+    ) -> Self {
+        Self {
+            metrics,
+            observer,
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            peer_addr,
+            accepted_at: Instant::now(),
+        }
+    }
+
+    /// Attributes subsequent events to the address from a PROXY
+    /// protocol header.
+    pub(crate) fn set_peer_addr(&mut self, peer_addr: SocketAddr) {
+        self.peer_addr = Some(peer_addr);
+    }
+
+    /// How long since the connection was accepted.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.accepted_at.elapsed()
+    }
+
+    pub(crate) fn emit(&self, kind: ConnectionEventKind) {
+        self.metrics.record(&kind);
+        if let Some(observer) = &self.observer {
+            observer.on_event(&ConnectionEvent {
+                id: self.id,
+                peer_addr: self.peer_addr,
+                kind,
+            });
+        }
+    }
+}
+
+/// Counts the application data read from and written to a TLS
+/// stream, for [`ConnectionEventKind::Closed`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ByteCounts {
+    read: Arc<AtomicU64>,
+    written: Arc<AtomicU64>,
+}
+
+impl ByteCounts {
+    pub(crate) fn add_read(&self, bytes: usize) {
+        self.read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_written(&self, bytes: usize) {
+        self.written.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    fn categorize(tls_error: TLSError) -> HandshakeFailure {
+        HandshakeFailure::from_error(&io::Error::new(io::ErrorKind::InvalidData, tls_error))
+    }
+
+    #[test]
+    fn tls_errors() {
+        assert_eq!(
+            categorize(TLSError::AlertReceived(AlertDescription::HandshakeFailure)),
+            HandshakeFailure::AlertReceived
+        );
+        assert_eq!(
+            categorize(TLSError::PeerIncompatibleError("no shared cipher".into())),
+            HandshakeFailure::PeerIncompatible
+        );
+        assert_eq!(
+            categorize(TLSError::NoApplicationProtocol),
+            HandshakeFailure::PeerIncompatible
+        );
+        assert_eq!(
+            categorize(TLSError::NoCertificatesPresented),
+            HandshakeFailure::ClientCertificate
+        );
+        assert_eq!(
+            categorize(TLSError::WebPKIError(webpki::Error::UnknownIssuer)),
+            HandshakeFailure::ClientCertificate
+        );
+        assert_eq!(
+            categorize(TLSError::CorruptMessage),
+            HandshakeFailure::Protocol
+        );
+        assert_eq!(
+            categorize(TLSError::PeerMisbehavedError("bad".into())),
+            HandshakeFailure::Protocol
+        );
+    }
+
+    #[test]
+    fn io_errors() {
+        for error in [
+            io::Error::from(io::ErrorKind::ConnectionReset),
+            io::Error::new(io::ErrorKind::UnexpectedEof, "tls handshake eof"),
+            io::Error::new(io::ErrorKind::InvalidData, "not a tls error"),
+        ] {
+            assert_eq!(HandshakeFailure::from_error(&error), HandshakeFailure::Io);
+        }
+    }
+
+    #[test]
+    fn byte_counts_are_shared_between_clones() {
+        let byte_counts = ByteCounts::default();
+        let clone = byte_counts.clone();
+        byte_counts.add_read(3);
+        clone.add_read(4);
+        clone.add_written(10);
+        assert_eq!(byte_counts.read(), 7);
+        assert_eq!(byte_counts.written(), 10);
+    }
+}
@@ -0,0 +1,304 @@
+//! Just enough DER to pick apart and assemble the handful of
+//! structures that rustls does not handle for us.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const BOOLEAN: u8 = 0x01;
+pub(crate) const INTEGER: u8 = 0x02;
+pub(crate) const BIT_STRING: u8 = 0x03;
+pub(crate) const OCTET_STRING: u8 = 0x04;
+pub(crate) const NULL: u8 = 0x05;
+pub(crate) const OBJECT_IDENTIFIER: u8 = 0x06;
+pub(crate) const ENUMERATED: u8 = 0x0a;
+pub(crate) const UTC_TIME: u8 = 0x17;
+pub(crate) const GENERALIZED_TIME: u8 = 0x18;
+pub(crate) const SEQUENCE: u8 = 0x30;
+pub(crate) const SET: u8 = 0x31;
+pub(crate) const CONTEXT_0: u8 = 0xa0;
+pub(crate) const CONTEXT_3: u8 = 0xa3;
+
+/// Reads one tag-length-value from the front of `input`, returning
+/// the tag, the value, and whatever follows it.
+pub(crate) fn read(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, input) = input.split_first()?;
+
+    let (len, input) = if first < 0x80 {
+        (first as usize, input)
+    } else {
+        let octets = (first & 0x7f) as usize;
+        if octets == 0 || octets > 4 || input.len() < octets {
+            return None;
+        }
+        let (len, input) = input.split_at(octets);
+        (len.iter().fold(0, |acc, &b| (acc << 8) | b as usize), input)
+    };
+
+    if input.len() < len {
+        return None;
+    }
+    let (value, rest) = input.split_at(len);
+    Some((tag, value, rest))
+}
+
+/// Reads one tag-length-value from the front of `input`, requiring
+/// that it have the expected tag.
+pub(crate) fn expect(tag: u8, input: &[u8]) -> Option<(&[u8], &[u8])> {
+    match read(input)? {
+        (actual, value, rest) if actual == tag => Some((value, rest)),
+        _ => None,
+    }
+}
+
+/// Like [`expect`], but returns the whole encoding rather than just
+/// the value.
+pub(crate) fn expect_encoded(tag: u8, input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (_, rest) = expect(tag, input)?;
+    Some(input.split_at(input.len() - rest.len()))
+}
+
+/// Reads a `UTCTime` or `GeneralizedTime` from the front of `input`,
+/// in the `Z`-terminated form that certificates use.
+pub(crate) fn read_time(input: &[u8]) -> Option<(SystemTime, &[u8])> {
+    let (tag, value, rest) = read(input)?;
+    let value = std::str::from_utf8(value).ok()?.strip_suffix('Z')?;
+
+    let (year, value) = match tag {
+        UTC_TIME => {
+            let year: u64 = value.get(..2)?.parse().ok()?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &value[2..],
+            )
+        }
+        GENERALIZED_TIME => (value.get(..4)?.parse().ok()?, &value[4..]),
+        _ => return None,
+    };
+
+    if value.len() != 10 || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |i: usize| value[i..i + 2].parse::<u64>().ok();
+    let (month, day) = (field(0)?, field(2)?);
+    let seconds = field(4)? * 3600 + field(6)? * 60 + field(8)?;
+
+    let days = days_since_epoch(year, month, day)?;
+    Some((
+        UNIX_EPOCH + Duration::from_secs(days * 86400 + seconds),
+        rest,
+    ))
+}
+
+/// The number of days from 1970-01-01 to the given date in the
+/// proleptic gregorian calendar, for dates since then.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> Option<u64> {
+    let leap = matches!(
+        (year % 4, year % 100, year % 400),
+        (0, 1..=99, _) | (_, _, 0)
+    );
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+
+    // Counting years from March puts the leap day at the end.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    (era * 146_097 + day_of_era).checked_sub(719_468)
+}
+
+/// Formats a time such as a certificate's notAfter for an error
+/// message, as `2024-01-31 23:59:59 UTC`.
+pub(crate) fn format_time(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // The inverse of days_since_epoch, again counting years from March.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Encodes `value` as a tag-length-value.
+pub(crate) fn write(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if value.len() < 0x80 {
+        out.push(value.len() as u8);
+    } else {
+        let len = value.len().to_be_bytes();
+        let len = &len[len.iter().take_while(|&&b| b == 0).count()..];
+        out.push(0x80 | len.len() as u8);
+        out.extend_from_slice(len);
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(tag: u8, value: &str) -> Option<SystemTime> {
+        let encoded = write(tag, value.as_bytes());
+        let (time, rest) = read_time(&encoded)?;
+        assert!(rest.is_empty());
+        Some(time)
+    }
+
+    fn formatted(tag: u8, value: &str) -> Option<String> {
+        time(tag, value).map(format_time)
+    }
+
+    #[test]
+    fn read_short_and_long_lengths() {
+        assert_eq!(
+            read(&[INTEGER, 1, 5, 0xff]),
+            Some((INTEGER, &[5][..], &[0xff][..]))
+        );
+
+        let value = vec![7; 300];
+        let encoded = write(OCTET_STRING, &value);
+        assert_eq!(&encoded[..4], &[OCTET_STRING, 0x82, 0x01, 0x2c]);
+        assert_eq!(read(&encoded), Some((OCTET_STRING, &value[..], &[][..])));
+
+        assert_eq!(read(&write(NULL, &[])), Some((NULL, &[][..], &[][..])));
+    }
+
+    #[test]
+    fn read_truncated_or_garbage() {
+        assert_eq!(read(&[]), None);
+        assert_eq!(read(&[SEQUENCE]), None);
+        assert_eq!(read(&[SEQUENCE, 3, 1, 2]), None);
+        assert_eq!(read(&[SEQUENCE, 0x82, 0x01]), None);
+        assert_eq!(read(&[SEQUENCE, 0x82, 0x01, 0x00, 0x00]), None);
+        // indefinite lengths are not DER
+        assert_eq!(read(&[SEQUENCE, 0x80, 0x00, 0x00]), None);
+        assert_eq!(read(&[SEQUENCE, 0x85, 1, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn expect_checks_the_tag() {
+        let input = [write(INTEGER, &[1]), write(NULL, &[])].concat();
+        assert_eq!(expect(INTEGER, &input), Some((&[1][..], &input[3..])));
+        assert_eq!(expect(SEQUENCE, &input), None);
+        assert_eq!(
+            expect_encoded(INTEGER, &input),
+            Some((&input[..3], &input[3..]))
+        );
+    }
+
+    #[test]
+    fn utc_time_years() {
+        assert_eq!(
+            formatted(UTC_TIME, "491231235959Z").unwrap(),
+            "2049-12-31 23:59:59 UTC"
+        );
+        assert_eq!(
+            formatted(UTC_TIME, "991231235959Z").unwrap(),
+            "1999-12-31 23:59:59 UTC"
+        );
+        // 1950, which is before any time we need to represent
+        assert_eq!(time(UTC_TIME, "500101000000Z"), None);
+        assert_eq!(time(UTC_TIME, "700101000000Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            formatted(GENERALIZED_TIME, "20500101000000Z").unwrap(),
+            "2050-01-01 00:00:00 UTC"
+        );
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(
+            formatted(GENERALIZED_TIME, "20000229120000Z").unwrap(),
+            "2000-02-29 12:00:00 UTC"
+        );
+        assert_eq!(
+            formatted(UTC_TIME, "240229000000Z").unwrap(),
+            "2024-02-29 00:00:00 UTC"
+        );
+        assert_eq!(time(UTC_TIME, "230229000000Z"), None);
+        assert_eq!(time(GENERALIZED_TIME, "21000229000000Z"), None);
+        assert_eq!(
+            formatted(GENERALIZED_TIME, "21000301000000Z").unwrap(),
+            "2100-03-01 00:00:00 UTC"
+        );
+
+        let feb_28 = time(GENERALIZED_TIME, "20240228000000Z").unwrap();
+        let mar_1 = time(GENERALIZED_TIME, "20240301000000Z").unwrap();
+        assert_eq!(mar_1.duration_since(feb_28).unwrap().as_secs(), 2 * 86400);
+    }
+
+    #[test]
+    fn known_dates() {
+        assert_eq!(days_since_epoch(1970, 1, 1), Some(0));
+        assert_eq!(days_since_epoch(2000, 3, 1), Some(11_017));
+        assert_eq!(days_since_epoch(2038, 1, 19), Some(24_855));
+        assert_eq!(days_since_epoch(1969, 12, 31), None);
+    }
+
+    #[test]
+    fn format_time_round_trips() {
+        for date in [
+            "19700101000000Z",
+            "19991231235959Z",
+            "20000101000000Z",
+            "20240229235959Z",
+            "20991231000000Z",
+            "24000229000000Z",
+        ] {
+            let formatted = formatted(GENERALIZED_TIME, date).unwrap();
+            let digits: String = formatted.chars().filter(char::is_ascii_digit).collect();
+            assert_eq!(format!("{}Z", digits), date);
+        }
+    }
+
+    #[test]
+    fn invalid_times() {
+        for value in [
+            "20241301000000Z",
+            "20240001000000Z",
+            "20240100000000Z",
+            "20240431000000Z",
+            "20240132000000Z",
+            "20240101000000",
+            "2024010100000Z",
+            "2024010100000aZ",
+            "19691231235959Z",
+            "+0240101000000Z",
+        ] {
+            assert_eq!(time(GENERALIZED_TIME, value), None, "{}", value);
+        }
+        assert_eq!(time(UTC_TIME, "2401010000Z"), None);
+        assert_eq!(time(OCTET_STRING, "240101000000Z"), None);
+        assert_eq!(read_time(&[UTC_TIME, 13, b'2', b'4']), None);
+    }
+}
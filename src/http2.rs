@@ -0,0 +1,438 @@
+//! Serves connections that negotiated http/2 with ALPN, translating
+//! between h2's types and the http-types that tide speaks.
+
+use crate::{ConnectionContext, ShutdownHandle, TlsStreamWrapper};
+
+use tide::http::headers::{HeaderName, CONTENT_LENGTH};
+use tide::http::{Body, Method, Request, Response, StatusCode, Url, Version};
+use tide::Server;
+
+use async_std::{future, io, task};
+use futures_lite::{ready, AsyncRead, AsyncReadExt, AsyncWrite, FutureExt};
+
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::RecvStream;
+
+use rustls::{ServerSession, Session};
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// The ALPN protocol id for http/2.
+pub(crate) const H2: &[u8] = b"h2";
+
+/// The ALPN protocol id for http/1.1.
+pub(crate) const HTTP_1_1: &[u8] = b"http/1.1";
+
+/// Whether the client chose http/2 during the handshake.
+pub(crate) fn negotiated(session: &ServerSession) -> bool {
+    session.get_alpn_protocol() == Some(H2)
+}
+
+/// Headers that only have meaning for an http/1.1 connection, which
+/// an http/2 message must not contain.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Serves each stream on an http/2 connection as a request to `app`
+/// until the client closes the connection. Once the listener is shut
+/// down, the client is sent a GOAWAY and the connection closes as
+/// soon as its in-flight streams complete.
+pub(crate) async fn serve<State: Clone + Send + Sync + 'static>(
+    app: Server<State>,
+    stream: TlsStreamWrapper,
+    context: ConnectionContext,
+    shutdown: &ShutdownHandle,
+) -> Result<(), h2::Error> {
+    let mut connection = h2::server::handshake(Compat(stream)).await?;
+    let context = Arc::new(context);
+    let mut shutting_down = false;
+
+    loop {
+        let next = async { Some(connection.accept().await) }
+            .or(async {
+                if shutting_down {
+                    future::pending::<()>().await;
+                }
+                shutdown.wait().await;
+                None
+            })
+            .await;
+
+        match next {
+            None => {
+                connection.graceful_shutdown();
+                shutting_down = true;
+            }
+            Some(None) => return Ok(()),
+            Some(Some(Ok((request, respond)))) => {
+                task::spawn(serve_stream(app.clone(), context.clone(), request, respond));
+            }
+            Some(Some(Err(error))) => return Err(error),
+        }
+    }
+}
+
+async fn serve_stream<State: Clone + Send + Sync + 'static>(
+    app: Server<State>,
+    context: Arc<ConnectionContext>,
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+) {
+    let (parts, body) = request.into_parts();
+    let req = match convert_request(parts) {
+        Ok(mut req) => {
+            let len = req
+                .header(CONTENT_LENGTH)
+                .and_then(|values| values.last().as_str().parse().ok());
+            req.set_body(Body::from_reader(
+                io::BufReader::new(RecvBody {
+                    stream: body,
+                    chunk: Bytes::new(),
+                }),
+                len,
+            ));
+            context.apply(&mut req);
+            req
+        }
+        Err(error) => {
+            tide::log::error!("invalid http/2 request", { error: error.to_string() });
+            let response = http::Response::builder()
+                .status(400)
+                .body(())
+                .expect("a status-only response is always valid");
+            let _ = respond.send_response(response, true);
+            return;
+        }
+    };
+
+    if let Err(error) = respond_to(app, req, respond).await {
+        tide::log::error!("http/2 error", { error: error.to_string() });
+    }
+}
+
+async fn respond_to<State: Clone + Send + Sync + 'static>(
+    app: Server<State>,
+    req: Request,
+    mut respond: SendResponse<Bytes>,
+) -> tide::http::Result<()> {
+    let is_head = req.method() == Method::Head;
+    let mut res: Response = match app.respond(req).await {
+        Ok(res) => res,
+        Err(error) => {
+            tide::log::error!("http/2 error", { error: error.to_string() });
+            Response::new(StatusCode::InternalServerError)
+        }
+    };
+
+    let mut response = http::Response::builder().status(u16::from(res.status()));
+    for (name, values) in res.iter() {
+        // the body decides the content length, below
+        if CONNECTION_HEADERS.contains(&name.as_str()) || *name == CONTENT_LENGTH {
+            continue;
+        }
+        for value in values {
+            response = response.header(name.as_str(), value.as_str());
+        }
+    }
+
+    let mut body = res.take_body();
+    if let Some(len) = body.len() {
+        response = response.header(CONTENT_LENGTH.as_str(), len);
+    }
+
+    let end_of_stream = is_head || body.is_empty() == Some(true);
+    let mut send = respond.send_response(response.body(())?, end_of_stream)?;
+    if end_of_stream {
+        return Ok(());
+    }
+
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let len = body.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+
+        let mut chunk = Bytes::copy_from_slice(&buf[..len]);
+        while !chunk.is_empty() {
+            send.reserve_capacity(chunk.len());
+            let capacity = future::poll_fn(|cx| send.poll_capacity(cx))
+                .await
+                .ok_or_else(|| io::Error::other("http/2 stream closed by the client"))??;
+            send.send_data(chunk.split_to(capacity.min(chunk.len())), false)?;
+        }
+    }
+
+    send.send_data(Bytes::new(), true)?;
+    Ok(())
+}
+
+/// Builds a request from the head of an h2 request, leaving the body
+/// to be set by the caller.
+fn convert_request(parts: http::request::Parts) -> tide::http::Result<Request> {
+    let method: Method = parts.method.as_str().parse()?;
+    let url = match parts.uri.authority() {
+        Some(_) => Url::parse(&parts.uri.to_string())?,
+        None => {
+            let host = parts
+                .headers
+                .get("host")
+                .ok_or_else(|| io::Error::other("missing :authority and host"))?
+                .to_str()?;
+            Url::parse(&format!("https://{}{}", host, parts.uri))?
+        }
+    };
+
+    let mut req = Request::new(method, url);
+    req.set_version(Some(Version::Http2_0));
+    for (name, value) in &parts.headers {
+        req.append_header(
+            HeaderName::from_bytes(name.as_str().as_bytes().to_vec())?,
+            value.to_str()?,
+        );
+    }
+
+    Ok(req)
+}
+
+/// Reads a request body from an h2 stream, releasing flow control
+/// capacity back to the client as the body is consumed.
+struct RecvBody {
+    stream: RecvStream,
+    chunk: Bytes,
+}
+
+impl AsyncRead for RecvBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        while self.chunk.is_empty() {
+            match ready!(self.stream.poll_data(cx)) {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(error)) => return Poll::Ready(Err(io::Error::other(error))),
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        let _ = self.stream.flow_control().release_capacity(len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+/// Adapts a futures-io stream to the tokio io traits that h2 is
+/// written against.
+struct Compat<T>(T);
+
+impl<T: AsyncRead + Unpin> tokio::io::AsyncRead for Compat<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = ready!(Pin::new(&mut self.0).poll_read(cx, buf.initialize_unfilled()))?;
+        buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> tokio::io::AsyncWrite for Compat<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::{TcpListener, TcpStream};
+
+    fn parts(request: http::request::Builder) -> http::request::Parts {
+        request.body(()).unwrap().into_parts().0
+    }
+
+    /// Sends `request` over a real h2 connection to `app`, returning the
+    /// response head, whether the headers ended the stream, and the
+    /// body.
+    async fn send(
+        app: Server<()>,
+        request: http::Request<()>,
+    ) -> (http::response::Parts, bool, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = h2::server::handshake(Compat(stream)).await.unwrap();
+            let context = Arc::new(ConnectionContext::plaintext(None, None));
+            while let Some(Ok((request, respond))) = connection.accept().await {
+                task::spawn(serve_stream(app.clone(), context.clone(), request, respond));
+            }
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut client, connection) = h2::client::handshake(Compat(stream)).await.unwrap();
+        task::spawn(connection);
+
+        let (response, _) = client.send_request(request, true).unwrap();
+        let (parts, mut body) = response.await.unwrap().into_parts();
+        let end_of_stream = body.is_end_stream();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.unwrap();
+            let _ = body.flow_control().release_capacity(chunk.len());
+            data.extend_from_slice(&chunk);
+        }
+        (parts, end_of_stream, data)
+    }
+
+    fn app() -> Server<()> {
+        let mut app = tide::new();
+        app.at("/").get(|_| async { Ok("hello http/2") });
+        app.at("/empty")
+            .get(|_| async { Ok(tide::Response::new(StatusCode::NoContent)) });
+        app.at("/length").get(|_| async {
+            Ok(tide::Response::builder(StatusCode::Ok)
+                .header("content-length", "1234")
+                .body("hello http/2")
+                .build())
+        });
+        app.at("/headers").get(|_| async {
+            Ok(tide::Response::builder(StatusCode::Ok)
+                .header("connection", "keep-alive")
+                .header("keep-alive", "timeout=5")
+                .header("upgrade", "websocket")
+                .header("x-kept", "yes")
+                .build())
+        });
+        app
+    }
+
+    fn get(path: &str) -> http::request::Builder {
+        http::Request::get(format!("https://localhost{}", path))
+    }
+
+    #[test]
+    fn url_from_authority() {
+        let req = convert_request(parts(
+            http::Request::post("https://example.com:8443/a/b?c=d")
+                .header("host", "ignored.example.com")
+                .header("x-forwarded-for", "10.0.0.1"),
+        ))
+        .unwrap();
+
+        assert_eq!(req.method(), Method::Post);
+        assert_eq!(req.url().as_str(), "https://example.com:8443/a/b?c=d");
+        assert_eq!(req.version(), Some(Version::Http2_0));
+        assert_eq!(req["x-forwarded-for"], "10.0.0.1");
+    }
+
+    #[test]
+    fn url_from_host() {
+        let req = convert_request(parts(
+            http::Request::get("/a/b?c=d").header("host", "example.com:8443"),
+        ))
+        .unwrap();
+        assert_eq!(req.url().as_str(), "https://example.com:8443/a/b?c=d");
+    }
+
+    #[test]
+    fn invalid_requests() {
+        assert!(convert_request(parts(http::Request::get("/a/b"))).is_err());
+
+        let non_utf8 = http::HeaderValue::from_bytes(b"\xff").unwrap();
+        assert!(convert_request(parts(get("/").header("x-bytes", non_utf8.clone()))).is_err());
+        assert!(convert_request(parts(http::Request::get("/").header("host", non_utf8))).is_err());
+    }
+
+    #[test]
+    fn serves_a_body() {
+        task::block_on(async {
+            let (parts, end_of_stream, body) = send(app(), get("/").body(()).unwrap()).await;
+            assert_eq!(parts.status, 200);
+            assert_eq!(parts.headers["content-length"], "12");
+            assert!(!end_of_stream);
+            assert_eq!(body, b"hello http/2");
+        });
+    }
+
+    #[test]
+    fn head_and_empty_responses_end_the_stream_with_the_headers() {
+        task::block_on(async {
+            let head = http::Request::head("https://localhost/").body(()).unwrap();
+            let (parts, end_of_stream, body) = send(app(), head).await;
+            assert_eq!(parts.status, 200);
+            assert_eq!(parts.headers["content-length"], "12");
+            assert!(end_of_stream);
+            assert!(body.is_empty());
+
+            let (parts, end_of_stream, body) = send(app(), get("/empty").body(()).unwrap()).await;
+            assert_eq!(parts.status, 204);
+            assert!(end_of_stream);
+            assert!(body.is_empty());
+        });
+    }
+
+    #[test]
+    fn content_length_comes_from_the_body() {
+        task::block_on(async {
+            let (parts, _, body) = send(app(), get("/length").body(()).unwrap()).await;
+            let lengths: Vec<_> = parts.headers.get_all("content-length").iter().collect();
+            assert_eq!(lengths, ["12"]);
+            assert_eq!(body, b"hello http/2");
+
+            let head = http::Request::head("https://localhost/length")
+                .body(())
+                .unwrap();
+            let (parts, end_of_stream, _) = send(app(), head).await;
+            let lengths: Vec<_> = parts.headers.get_all("content-length").iter().collect();
+            assert_eq!(lengths, ["12"]);
+            assert!(end_of_stream);
+        });
+    }
+
+    #[test]
+    fn strips_connection_headers() {
+        task::block_on(async {
+            let (parts, _, _) = send(app(), get("/headers").body(()).unwrap()).await;
+            assert_eq!(parts.status, 200);
+            assert_eq!(parts.headers["x-kept"], "yes");
+            for name in CONNECTION_HEADERS {
+                assert!(!parts.headers.contains_key(*name), "{}", name);
+            }
+        });
+    }
+
+    #[test]
+    fn invalid_request_is_a_bad_request() {
+        task::block_on(async {
+            let non_utf8 = http::HeaderValue::from_bytes(b"\xff").unwrap();
+            let request = get("/").header("x-bytes", non_utf8).body(()).unwrap();
+            let (parts, end_of_stream, _) = send(app(), request).await;
+            assert_eq!(parts.status, 400);
+            assert!(end_of_stream);
+        });
+    }
+}
@@ -0,0 +1,110 @@
+use crate::ShutdownHandle;
+
+use tide::http::headers::LOCATION;
+use tide::http::{Method, Request, Response, StatusCode};
+
+use async_std::net::TcpStream;
+use futures_lite::FutureExt;
+
+/// Serves a plaintext http connection, answering every request on it
+/// with a redirect to the same host and path on `https_port`, until
+/// the client closes it or the listener is shut down.
+pub(crate) async fn serve(stream: TcpStream, https_port: u16, shutdown: &ShutdownHandle) {
+    let result = async_h1::accept(
+        stream,
+        move |req| async move { Ok(redirect(&req, https_port)) },
+    )
+    .or(async {
+        shutdown.wait().await;
+        Ok(())
+    })
+    .await;
+
+    if let Err(error) = result {
+        tide::log::error!("async-h1 error", { error: error.to_string() });
+    }
+}
+
+/// GET and HEAD are redirected with a 301 for the sake of older
+/// clients, and everything else with a 308 so that the request is
+/// repeated with the same method and body.
+fn redirect(req: &Request, https_port: u16) -> Response {
+    let mut url = req.url().clone();
+    // Neither of these can fail for an http url.
+    let _ = url.set_scheme("https");
+    let _ = url.set_port(Some(https_port).filter(|&port| port != 443));
+
+    let status = match req.method() {
+        Method::Get | Method::Head => StatusCode::MovedPermanently,
+        _ => StatusCode::PermanentRedirect,
+    };
+
+    let mut res = Response::new(status);
+    res.insert_header(LOCATION, url.as_str());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::Url;
+
+    fn location(method: Method, url: &str, https_port: u16) -> (StatusCode, String) {
+        let req = Request::new(method, Url::parse(url).unwrap());
+        let res = redirect(&req, https_port);
+        (res.status(), res[LOCATION].as_str().to_string())
+    }
+
+    #[test]
+    fn status_by_method() {
+        for method in [Method::Get, Method::Head] {
+            assert_eq!(
+                location(method, "http://example.com/", 443).0,
+                StatusCode::MovedPermanently
+            );
+        }
+
+        for method in [Method::Post, Method::Put, Method::Delete, Method::Patch] {
+            assert_eq!(
+                location(method, "http://example.com/", 443).0,
+                StatusCode::PermanentRedirect
+            );
+        }
+    }
+
+    #[test]
+    fn default_https_port_is_omitted() {
+        assert_eq!(
+            location(Method::Get, "http://example.com/", 443).1,
+            "https://example.com/"
+        );
+        assert_eq!(
+            location(Method::Get, "http://example.com:8080/", 443).1,
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn other_https_ports_are_kept() {
+        assert_eq!(
+            location(Method::Get, "http://example.com/", 8443).1,
+            "https://example.com:8443/"
+        );
+        assert_eq!(
+            location(Method::Get, "http://example.com:8080/", 4433).1,
+            "https://example.com:4433/"
+        );
+    }
+
+    #[test]
+    fn path_and_query_are_kept() {
+        assert_eq!(
+            location(Method::Post, "http://example.com/a/b%20c?d=e&f", 443).1,
+            "https://example.com/a/b%20c?d=e&f"
+        );
+        assert_eq!(
+            location(Method::Get, "http://127.0.0.1:8080/a?b=c", 8443).1,
+            "https://127.0.0.1:8443/a?b=c"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use async_std::io;
+
+use rustls::KeyLog;
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Where to write TLS session secrets for
+/// [`TlsListenerBuilder::key_log_file`](crate::TlsListenerBuilder::key_log_file)
+/// and
+/// [`TlsListenerBuilder::key_log_from_env`](crate::TlsListenerBuilder::key_log_from_env).
+#[derive(Debug, Clone)]
+pub(crate) enum KeyLogDestination {
+    Path(PathBuf),
+    Env,
+}
+
+impl KeyLogDestination {
+    /// The file to write to, which is `None` when the destination is
+    /// the `SSLKEYLOGFILE` environment variable and that is not set.
+    fn path(&self) -> Option<PathBuf> {
+        match self {
+            Self::Path(path) => Some(path.clone()),
+            Self::Env => std::env::var_os("SSLKEYLOGFILE")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+        }
+    }
+}
+
+/// A [`KeyLog`] that appends the secrets for each TLS session to a
+/// file in the NSS key log format, which Wireshark can use to decrypt
+/// captured traffic.
+#[derive(Debug)]
+pub(crate) struct KeyLogWriter {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl KeyLogWriter {
+    /// Opens the file for `destination`, returning `None` if there is
+    /// none. Since this makes the listener's traffic decryptable by
+    /// anyone who can read the file, it is logged as a warning.
+    pub(crate) fn open(destination: &KeyLogDestination) -> io::Result<Option<Self>> {
+        let path = match destination.path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let mut options = OpenOptions::new();
+        options.append(true).create(true);
+        // a new file is readable only by its owner, though an existing
+        // one keeps its permissions
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let file = options.open(&path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("unable to open key log file {}: {}", path.display(), err),
+            )
+        })?;
+
+        tide::log::warn!(
+            "LOGGING TLS SESSION SECRETS: anyone who can read the key log file can decrypt \
+             traffic on this listener, so this must never be enabled in production",
+            { key_log_file: path.display().to_string() }
+        );
+
+        Ok(Some(Self {
+            path,
+            file: Mutex::new(file),
+        }))
+    }
+}
+
+impl KeyLog for KeyLogWriter {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let line = format!("{} {} {}\n", label, hex(client_random), hex(secret));
+        if let Err(error) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            tide::log::error!("unable to write to key log file", {
+                key_log_file: self.path.display().to_string(),
+                error: error.to_string(),
+            });
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_to_a_private_file() {
+        let path = std::env::temp_dir().join(format!("tide-rustls-keylog-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let writer = KeyLogWriter::open(&KeyLogDestination::Path(path.clone()))
+            .unwrap()
+            .unwrap();
+        writer.log("CLIENT_RANDOM", &[0x01, 0xab], &[0xff]);
+        writer.log("CLIENT_RANDOM", &[0x02], &[0x00, 0x10]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "CLIENT_RANDOM 01ab ff\nCLIENT_RANDOM 02 0010\n"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
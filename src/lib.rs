@@ -27,20 +27,77 @@
     unused_qualifications
 )]
 
+#[cfg(feature = "acme")]
+mod acme;
+#[cfg(feature = "acme")]
+mod acme_client;
+#[cfg(feature = "acme")]
+mod acme_config;
+mod active_connections;
+mod cert_expiry;
+mod certificates;
+mod client_certificates;
+mod connection_context;
+mod connection_observer;
 mod custom_tls_acceptor;
+mod der;
+#[cfg(feature = "http2")]
+mod http2;
+mod http_redirect;
+mod key_log;
+mod listener_metrics;
+mod listener_options;
+mod ocsp;
+mod plaintext_http;
+mod proxy_protocol;
+mod rate_limit;
+mod reloading_cert_resolver;
+#[cfg(feature = "self-signed")]
+mod self_signed_cert;
+mod server_config_options;
+mod session_resumption;
+mod shutdown_handle;
+mod sni_cert_resolver;
 mod tcp_connection;
+mod tls_connection_info;
 mod tls_listener;
 mod tls_listener_builder;
 mod tls_listener_config;
+mod tls_policy;
 mod tls_stream_wrapper;
 
+pub(crate) use active_connections::ConnectionGuard;
+pub(crate) use connection_context::ConnectionContext;
+pub(crate) use connection_observer::{ByteCounts, ConnectionEvents};
+pub(crate) use listener_options::ListenerOptions;
+pub(crate) use reloading_cert_resolver::ReloadingCertResolver;
+pub(crate) use server_config_options::ServerConfigOptions;
+pub(crate) use sni_cert_resolver::SniCertResolver;
 pub(crate) use tcp_connection::TcpConnection;
 pub(crate) use tls_listener_config::TlsListenerConfig;
 pub(crate) use tls_stream_wrapper::TlsStreamWrapper;
 
+#[cfg(feature = "acme")]
+pub use acme_config::AcmeConfig;
+pub use active_connections::{ActiveConnections, ConnectionLimitBehavior};
+pub use cert_expiry::CertExpiry;
+pub use client_certificates::ClientCertificates;
+pub use connection_observer::{
+    ConnectionEvent, ConnectionEventKind, ConnectionObserver, HandshakeFailure,
+};
 pub use custom_tls_acceptor::CustomTlsAcceptor;
+pub use listener_metrics::{Histogram, ListenerMetrics, MetricsSnapshot};
+pub use plaintext_http::PlaintextHttp;
+pub use proxy_protocol::ProxyProtocol;
+pub use rate_limit::{RateLimit, RateLimitBehavior};
+#[cfg(feature = "self-signed")]
+pub use self_signed_cert::SelfSignedCert;
+pub use session_resumption::TicketKeys;
+pub use shutdown_handle::ShutdownHandle;
+pub use tls_connection_info::TlsConnectionInfo;
 pub use tls_listener::TlsListener;
 pub use tls_listener_builder::TlsListenerBuilder;
+pub use tls_policy::TlsPolicy;
 
 pub use async_rustls;
 pub use rustls;
@@ -0,0 +1,512 @@
+use crate::{ActiveConnections, ConnectionEventKind, HandshakeFailure};
+
+use rustls::{CipherSuite, ProtocolVersion};
+
+use std::fmt::{Display, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The upper bounds of the handshake latency histogram buckets, in
+/// seconds, which are the default buckets of the Prometheus clients.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A handle to the metrics a [`TlsListener`](crate::TlsListener)
+/// keeps about the connections it serves. This is obtained with
+/// [`TlsListener::metrics`](crate::TlsListener::metrics).
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
+/// # fn main() -> tide::Result<()> { async_std::task::block_on(async {
+/// let mut app = tide::new();
+///
+/// let listener = TlsListener::build()
+///     .addrs("localhost:4433")
+///     .cert("./tls/localhost-4433.cert")
+///     .key("./tls/localhost-4433.key")
+///     .finish()?;
+///
+/// let metrics = listener.metrics();
+/// app.at("/metrics").get(move |_| {
+///     let metrics = metrics.snapshot().render_prometheus();
+///     async move { Ok(metrics) }
+/// });
+///
+/// # if false {
+/// app.listen(listener).await?;
+/// # }
+/// # Ok(()) }) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ListenerMetrics {
+    counts: Arc<Mutex<MetricsSnapshot>>,
+    active_connections: ActiveConnections,
+}
+
+impl ListenerMetrics {
+    pub(crate) fn new(active_connections: ActiveConnections) -> Self {
+        Self {
+            counts: Arc::default(),
+            active_connections,
+        }
+    }
+
+    /// The current value of every metric.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut snapshot = self.counts.lock().unwrap().clone();
+        snapshot.active_connections = self.active_connections.count();
+        snapshot
+    }
+
+    /// Counts a connection that was closed as soon as it was accepted,
+    /// which has no [`ConnectionEventKind`] of its own.
+    pub(crate) fn record_rejection(&self, rejection: Rejection) {
+        let mut counts = self.counts.lock().unwrap();
+        match rejection {
+            Rejection::RateLimit => counts.rate_limited_connections += 1,
+            Rejection::ConnectionLimit => counts.connections_over_limit += 1,
+        }
+    }
+
+    pub(crate) fn record(&self, event: &ConnectionEventKind) {
+        let mut counts = self.counts.lock().unwrap();
+        match event {
+            ConnectionEventKind::Accepted => counts.accepted_connections += 1,
+
+            ConnectionEventKind::HandshakeCompleted { duration, info } => {
+                counts.handshake_successes += 1;
+                counts.handshake_latency.observe(*duration);
+                if let Some(protocol_version) = info.protocol_version() {
+                    increment(&mut counts.protocol_versions, protocol_version);
+                }
+                if let Some(cipher_suite) = info.cipher_suite() {
+                    increment(&mut counts.cipher_suites, cipher_suite);
+                }
+            }
+
+            ConnectionEventKind::HandshakeFailed {
+                duration, reason, ..
+            } => {
+                counts.handshake_latency.observe(*duration);
+                increment(&mut counts.handshake_failures, *reason);
+            }
+
+            ConnectionEventKind::HttpError { .. } => counts.http_errors += 1,
+
+            ConnectionEventKind::Closed { .. } => {}
+        }
+    }
+}
+
+/// Why a connection was closed as soon as it was accepted.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Rejection {
+    RateLimit,
+    ConnectionLimit,
+}
+
+/// The values of the metrics in [`ListenerMetrics`] at one point in
+/// time.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    accepted_connections: u64,
+    rate_limited_connections: u64,
+    connections_over_limit: u64,
+    active_connections: usize,
+    handshake_successes: u64,
+    handshake_failures: Vec<(HandshakeFailure, u64)>,
+    handshake_latency: Histogram,
+    protocol_versions: Vec<(ProtocolVersion, u64)>,
+    cipher_suites: Vec<(CipherSuite, u64)>,
+    http_errors: u64,
+}
+
+impl MetricsSnapshot {
+    /// The number of tcp connections accepted, not including those
+    /// closed right away by a rate limit or the connection limit.
+    pub fn accepted_connections(&self) -> u64 {
+        self.accepted_connections
+    }
+
+    /// The number of tcp connections closed without a handshake
+    /// because their client exceeded
+    /// [`TlsListenerBuilder::connection_rate_limit`](crate::TlsListenerBuilder::connection_rate_limit)
+    /// or
+    /// [`TlsListenerBuilder::failed_handshake_rate_limit`](crate::TlsListenerBuilder::failed_handshake_rate_limit).
+    pub fn rate_limited_connections(&self) -> u64 {
+        self.rate_limited_connections
+    }
+
+    /// The number of tcp connections closed without a handshake
+    /// because [`TlsListenerBuilder::max_connections`](crate::TlsListenerBuilder::max_connections)
+    /// had been reached, with
+    /// [`ConnectionLimitBehavior::Close`](crate::ConnectionLimitBehavior::Close).
+    pub fn connections_over_limit(&self) -> u64 {
+        self.connections_over_limit
+    }
+
+    /// The number of connections currently open, as reported by
+    /// [`ActiveConnections`].
+    pub fn active_connections(&self) -> usize {
+        self.active_connections
+    }
+
+    /// The number of TLS handshakes that completed.
+    pub fn handshake_successes(&self) -> u64 {
+        self.handshake_successes
+    }
+
+    /// The number of TLS handshakes that failed, by the reason for
+    /// each failure. Reasons that have not happened are omitted.
+    pub fn handshake_failures(&self) -> &[(HandshakeFailure, u64)] {
+        &self.handshake_failures
+    }
+
+    /// How long after being accepted each connection's TLS handshake
+    /// completed or failed.
+    pub fn handshake_latency(&self) -> &Histogram {
+        &self.handshake_latency
+    }
+
+    /// The number of handshakes that negotiated each protocol version.
+    pub fn protocol_versions(&self) -> &[(ProtocolVersion, u64)] {
+        &self.protocol_versions
+    }
+
+    /// The number of handshakes that negotiated each cipher suite.
+    pub fn cipher_suites(&self) -> &[(CipherSuite, u64)] {
+        &self.cipher_suites
+    }
+
+    /// The number of connections closed by an error from the http/1.1
+    /// or http/2 server.
+    pub fn http_errors(&self) -> u64 {
+        self.http_errors
+    }
+
+    /// Renders these metrics in the Prometheus text exposition format,
+    /// with names prefixed by `tide_rustls_`, to be served from a
+    /// metrics endpoint.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = Exposition::default();
+
+        out.family(
+            "connections_accepted_total",
+            "counter",
+            "Tcp connections accepted.",
+        )
+        .sample("", "", self.accepted_connections);
+
+        out.family(
+            "connections_rejected_total",
+            "counter",
+            "Tcp connections closed without a handshake, by reason.",
+        )
+        .sample("", r#"reason="rate_limit""#, self.rate_limited_connections)
+        .sample(
+            "",
+            r#"reason="max_connections""#,
+            self.connections_over_limit,
+        );
+
+        out.family("connections_active", "gauge", "Connections currently open.")
+            .sample("", "", self.active_connections);
+
+        out.family("handshakes_total", "counter", "TLS handshakes, by result.")
+            .sample("", r#"result="success""#, self.handshake_successes);
+        for (reason, count) in &self.handshake_failures {
+            let labels = format!(r#"result="failure",reason="{}""#, failure_label(*reason));
+            out.sample("", &labels, count);
+        }
+
+        out.family(
+            "handshake_duration_seconds",
+            "histogram",
+            "Time from accepting a connection to completing or failing its TLS handshake.",
+        );
+        for (le, count) in LATENCY_BUCKETS.iter().zip(&self.handshake_latency.buckets) {
+            out.sample("_bucket", &format!(r#"le="{}""#, le), count);
+        }
+        let latency = &self.handshake_latency;
+        out.sample("_bucket", r#"le="+Inf""#, latency.count)
+            .sample("_sum", "", latency.sum.as_secs_f64())
+            .sample("_count", "", latency.count);
+
+        out.family(
+            "protocol_versions_total",
+            "counter",
+            "Negotiated TLS versions.",
+        );
+        for (protocol_version, count) in &self.protocol_versions {
+            out.sample("", &format!(r#"version="{:?}""#, protocol_version), count);
+        }
+
+        out.family(
+            "cipher_suites_total",
+            "counter",
+            "Negotiated cipher suites.",
+        );
+        for (cipher_suite, count) in &self.cipher_suites {
+            out.sample("", &format!(r#"cipher_suite="{:?}""#, cipher_suite), count);
+        }
+
+        out.family(
+            "http_errors_total",
+            "counter",
+            "Connections closed by http errors.",
+        )
+        .sample("", "", self.http_errors);
+
+        out.text
+    }
+}
+
+/// A histogram of durations, with cumulative counts for each bucket
+/// as Prometheus expects.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+    /// The upper bound of each bucket, along with the number of
+    /// observations that were no greater than it.
+    pub fn buckets(&self) -> Vec<(Duration, u64)> {
+        LATENCY_BUCKETS
+            .iter()
+            .map(|le| Duration::from_secs_f64(*le))
+            .zip(self.buckets.iter().copied())
+            .collect()
+    }
+
+    /// The number of observations.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The total of every observation.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(&mut self.buckets) {
+            if seconds <= *le {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += duration;
+    }
+}
+
+/// Counts are kept in the order each key was first seen, since the
+/// rustls enums cannot be hashed or ordered.
+fn increment<K: PartialEq>(counts: &mut Vec<(K, u64)>, key: K) {
+    match counts.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((key, 1)),
+    }
+}
+
+fn failure_label(reason: HandshakeFailure) -> &'static str {
+    match reason {
+        HandshakeFailure::Timeout => "timeout",
+        HandshakeFailure::ProxyProtocol => "proxy_protocol",
+        HandshakeFailure::AlertReceived => "alert_received",
+        HandshakeFailure::PeerIncompatible => "peer_incompatible",
+        HandshakeFailure::ClientCertificate => "client_certificate",
+        HandshakeFailure::Protocol => "protocol",
+        HandshakeFailure::Io => "io",
+    }
+}
+
+/// Writes metrics in the Prometheus text exposition format, one family
+/// at a time.
+#[derive(Default)]
+struct Exposition {
+    text: String,
+    family: &'static str,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &'static str, kind: &str, help: &str) -> &mut Self {
+        self.family = name;
+        let _ = writeln!(self.text, "# HELP tide_rustls_{} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE tide_rustls_{} {}", name, kind);
+        self
+    }
+
+    /// Writes a sample for the current family, with a suffix such as
+    /// `_bucket` for the parts of a histogram.
+    fn sample(&mut self, suffix: &str, labels: &str, value: impl Display) -> &mut Self {
+        let _ = if labels.is_empty() {
+            writeln!(self.text, "tide_rustls_{}{} {}", self.family, suffix, value)
+        } else {
+            writeln!(
+                self.text,
+                "tide_rustls_{}{}{{{}}} {}",
+                self.family, suffix, labels, value
+            )
+        };
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        for duration in [ms(1), ms(5), ms(30), ms(300), Duration::from_secs(20)] {
+            histogram.observe(duration);
+        }
+
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.sum(), ms(20_336));
+        let counts: Vec<u64> = histogram
+            .buckets()
+            .iter()
+            .map(|(_, count)| *count)
+            .collect();
+        assert_eq!(counts, [2, 2, 2, 3, 3, 3, 4, 4, 4, 4, 4]);
+        assert_eq!(histogram.buckets()[0].0, ms(5));
+        assert_eq!(histogram.buckets()[10].0, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn renders_an_empty_snapshot() {
+        assert_eq!(
+            MetricsSnapshot::default().render_prometheus(),
+            "\
+# HELP tide_rustls_connections_accepted_total Tcp connections accepted.
+# TYPE tide_rustls_connections_accepted_total counter
+tide_rustls_connections_accepted_total 0
+# HELP tide_rustls_connections_rejected_total Tcp connections closed without a handshake, by reason.
+# TYPE tide_rustls_connections_rejected_total counter
+tide_rustls_connections_rejected_total{reason=\"rate_limit\"} 0
+tide_rustls_connections_rejected_total{reason=\"max_connections\"} 0
+# HELP tide_rustls_connections_active Connections currently open.
+# TYPE tide_rustls_connections_active gauge
+tide_rustls_connections_active 0
+# HELP tide_rustls_handshakes_total TLS handshakes, by result.
+# TYPE tide_rustls_handshakes_total counter
+tide_rustls_handshakes_total{result=\"success\"} 0
+# HELP tide_rustls_handshake_duration_seconds Time from accepting a connection to completing or failing its TLS handshake.
+# TYPE tide_rustls_handshake_duration_seconds histogram
+tide_rustls_handshake_duration_seconds_bucket{le=\"0.005\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"0.01\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"0.025\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"0.05\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"0.1\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"0.25\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"0.5\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"1\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"2.5\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"5\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"10\"} 0
+tide_rustls_handshake_duration_seconds_bucket{le=\"+Inf\"} 0
+tide_rustls_handshake_duration_seconds_sum 0
+tide_rustls_handshake_duration_seconds_count 0
+# HELP tide_rustls_protocol_versions_total Negotiated TLS versions.
+# TYPE tide_rustls_protocol_versions_total counter
+# HELP tide_rustls_cipher_suites_total Negotiated cipher suites.
+# TYPE tide_rustls_cipher_suites_total counter
+# HELP tide_rustls_http_errors_total Connections closed by http errors.
+# TYPE tide_rustls_http_errors_total counter
+tide_rustls_http_errors_total 0
+"
+        );
+    }
+
+    #[test]
+    fn renders_recorded_events() {
+        let metrics = ListenerMetrics::new(ActiveConnections::new());
+        for _ in 0..4 {
+            metrics.record(&ConnectionEventKind::Accepted);
+        }
+        metrics.record_rejection(Rejection::RateLimit);
+        metrics.record_rejection(Rejection::RateLimit);
+        metrics.record_rejection(Rejection::ConnectionLimit);
+        for (duration, reason) in [
+            (ms(20), HandshakeFailure::Timeout),
+            (ms(3), HandshakeFailure::AlertReceived),
+            (ms(200), HandshakeFailure::Timeout),
+            (ms(700), HandshakeFailure::ClientCertificate),
+        ] {
+            metrics.record(&ConnectionEventKind::HandshakeFailed {
+                duration,
+                reason,
+                error: String::new(),
+            });
+        }
+        metrics.record(&ConnectionEventKind::HttpError {
+            error: String::new(),
+        });
+
+        let mut snapshot = metrics.snapshot();
+        snapshot.protocol_versions =
+            vec![(ProtocolVersion::TLSv1_3, 3), (ProtocolVersion::TLSv1_2, 1)];
+        snapshot.cipher_suites = vec![(CipherSuite::TLS13_AES_128_GCM_SHA256, 3)];
+        snapshot.handshake_successes = 4;
+
+        let rendered = snapshot.render_prometheus();
+        let samples: Vec<&str> = rendered
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        assert_eq!(
+            samples,
+            [
+                "tide_rustls_connections_accepted_total 4",
+                "tide_rustls_connections_rejected_total{reason=\"rate_limit\"} 2",
+                "tide_rustls_connections_rejected_total{reason=\"max_connections\"} 1",
+                "tide_rustls_connections_active 0",
+                "tide_rustls_handshakes_total{result=\"success\"} 4",
+                "tide_rustls_handshakes_total{result=\"failure\",reason=\"timeout\"} 2",
+                "tide_rustls_handshakes_total{result=\"failure\",reason=\"alert_received\"} 1",
+                "tide_rustls_handshakes_total{result=\"failure\",reason=\"client_certificate\"} 1",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"0.005\"} 1",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"0.01\"} 1",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"0.025\"} 2",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"0.05\"} 2",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"0.1\"} 2",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"0.25\"} 3",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"0.5\"} 3",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"1\"} 4",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"2.5\"} 4",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"5\"} 4",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"10\"} 4",
+                "tide_rustls_handshake_duration_seconds_bucket{le=\"+Inf\"} 4",
+                "tide_rustls_handshake_duration_seconds_sum 0.923",
+                "tide_rustls_handshake_duration_seconds_count 4",
+                "tide_rustls_protocol_versions_total{version=\"TLSv1_3\"} 3",
+                "tide_rustls_protocol_versions_total{version=\"TLSv1_2\"} 1",
+                "tide_rustls_cipher_suites_total{cipher_suite=\"TLS13_AES_128_GCM_SHA256\"} 3",
+                "tide_rustls_http_errors_total 1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,25 @@
+use crate::rate_limit::RateLimiter;
+use crate::{ConnectionLimitBehavior, ConnectionObserver, PlaintextHttp, ProxyProtocol};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Options that govern how a [`TlsListener`](crate::TlsListener)
+/// accepts and serves tcp connections, independent of how TLS is
+/// configured.
+#[derive(Debug, Default)]
+pub(crate) struct ListenerOptions {
+    pub(crate) tcp_nodelay: Option<bool>,
+    pub(crate) tcp_ttl: Option<u32>,
+    pub(crate) drain_timeout: Option<Duration>,
+    pub(crate) handshake_timeout: Option<Duration>,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) connection_limit_behavior: ConnectionLimitBehavior,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) proxy_protocol: Option<ProxyProtocol>,
+    pub(crate) plaintext_http: Option<PlaintextHttp>,
+    pub(crate) cert_expiry_warnings: Option<Vec<Duration>>,
+    pub(crate) connection_observer: Option<Arc<dyn ConnectionObserver>>,
+    #[cfg(feature = "http2")]
+    pub(crate) http2: bool,
+}
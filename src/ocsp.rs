@@ -0,0 +1,677 @@
+//! Just enough OCSP (RFC 6960) to staple a response to the handshake,
+//! whether it is read from a file or fetched from the responder that
+//! the certificate names.
+
+use crate::certificates::TbsCertificate;
+use crate::der;
+
+use tide::http::url::{Position, Url};
+
+use async_std::io::{ReadExt, WriteExt};
+use async_std::net::TcpStream;
+use async_std::{future, io};
+
+use ring::digest::{digest, Algorithm, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+use rustls::Certificate;
+
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{Duration, SystemTime};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most to read from a responder, which is far more than any
+/// response for a single certificate needs.
+const MAX_RESPONSE_LEN: u64 = 64 * 1024;
+
+/// id-pe-authorityInfoAccess, 1.3.6.1.5.5.7.1.1
+const AUTHORITY_INFO_ACCESS_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+
+/// id-ad-ocsp, 1.3.6.1.5.5.7.48.1
+const OCSP_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+
+/// id-pkix-ocsp-basic, 1.3.6.1.5.5.7.48.1.1
+const BASIC_RESPONSE_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+/// id-sha1, 1.3.14.3.2.26, which every responder understands.
+const SHA1_OID: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+
+/// id-sha256, 2.16.840.1.101.3.4.2.1, which some responders use in
+/// their responses regardless.
+const SHA256_OID: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+/// The tag of the revoked CertStatus choice.
+const REVOKED: u8 = 0xa1;
+
+/// The tag of a uniformResourceIdentifier GeneralName.
+const URI: u8 = 0x86;
+
+/// Where the OCSP response to staple comes from.
+#[derive(Debug, Clone)]
+pub(crate) enum OcspSource {
+    /// A der-encoded response in a file, which is reloaded along with
+    /// the cert and key.
+    Path(PathBuf),
+
+    /// Fetched from this responder url, or from the one named in the
+    /// certificate's authority information access extension.
+    Fetch(Option<String>),
+}
+
+/// A successful response about a particular certificate.
+#[derive(Debug)]
+pub(crate) struct OcspResponse {
+    pub(crate) der: Vec<u8>,
+    this_update: SystemTime,
+    pub(crate) next_update: Option<SystemTime>,
+}
+
+impl OcspResponse {
+    /// Checks that `der` is a successful response about the first
+    /// certificate in `chain` that has not yet expired. When the chain
+    /// includes the issuer, the response must also identify the cert by
+    /// that issuer's name and key, and not just by serial number. The
+    /// signature is left for clients to verify, as they must anyway.
+    pub(crate) fn parse(der: Vec<u8>, chain: &[Certificate]) -> io::Result<Self> {
+        let cert = chain
+            .first()
+            .and_then(TbsCertificate::parse)
+            .ok_or_else(|| invalid("unreadable certificate"))?;
+        let issuer = match chain.get(1) {
+            Some(issuer) => Some(
+                TbsCertificate::parse(issuer)
+                    .ok_or_else(|| invalid("unreadable issuer certificate"))?,
+            ),
+            None => None,
+        };
+
+        match response_status(&der) {
+            Some(0) => {}
+            Some(status) => {
+                return Err(invalid(&format!(
+                    "ocsp response status is {}",
+                    status_name(status)
+                )))
+            }
+            None => return Err(invalid("invalid ocsp response")),
+        }
+
+        let (cert_status, this_update, next_update) = single_response(&der, &cert, issuer.as_ref())
+            .ok_or_else(|| invalid("invalid ocsp response, or not for this certificate"))?;
+
+        // Stapling the response is still right, since it tells clients
+        // not to trust the cert, but the cert needs replacing.
+        if cert_status == REVOKED {
+            tide::log::warn!("ocsp response says the certificate has been revoked", {
+                cert: cert.describe(),
+            });
+        }
+
+        if next_update.is_some_and(|next_update| next_update <= SystemTime::now()) {
+            return Err(invalid("ocsp response has expired"));
+        }
+
+        Ok(Self {
+            der,
+            this_update,
+            next_update,
+        })
+    }
+
+    /// When to fetch a replacement, which is halfway through this
+    /// response's validity period, or after a day if it does not say
+    /// when the next update will be.
+    pub(crate) fn refresh_at(&self) -> SystemTime {
+        let validity = self
+            .next_update
+            .and_then(|next_update| next_update.duration_since(self.this_update).ok())
+            .unwrap_or(Duration::from_secs(2 * 24 * 60 * 60));
+        self.this_update + validity / 2
+    }
+}
+
+/// Reads the response in `path`, checking it against the first
+/// certificate in `chain`.
+pub(crate) fn load(path: &Path, chain: &[Certificate]) -> io::Result<Vec<u8>> {
+    OcspResponse::parse(std::fs::read(path)?, chain)
+        .map(|response| response.der)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+}
+
+/// Requests a response for the first certificate in `chain`, which
+/// must be followed by the certificate that issued it.
+pub(crate) async fn fetch(
+    chain: &[Certificate],
+    responder: Option<&str>,
+) -> io::Result<OcspResponse> {
+    let cert = chain
+        .first()
+        .ok_or_else(|| invalid("empty certificate chain"))?;
+    let issuer = chain
+        .get(1)
+        .ok_or_else(|| invalid("fetching ocsp responses requires the issuer in the cert chain"))?;
+
+    let tbs = TbsCertificate::parse(cert).ok_or_else(|| invalid("unreadable certificate"))?;
+    let issuer_tbs =
+        TbsCertificate::parse(issuer).ok_or_else(|| invalid("unreadable issuer certificate"))?;
+
+    let url = match responder {
+        Some(responder) => responder.to_string(),
+        None => responder_url(&tbs)
+            .ok_or_else(|| invalid("certificate does not name an ocsp responder"))?,
+    };
+
+    let request = request(&tbs, &issuer_tbs).ok_or_else(|| invalid("unreadable issuer key"))?;
+    let der = future::timeout(FETCH_TIMEOUT, post(&url, request))
+        .await
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("ocsp request to {} timed out", url),
+            )
+        })??;
+
+    OcspResponse::parse(der, chain)
+}
+
+/// Builds an `OCSPRequest` for a single certificate, identified by its
+/// serial number and hashes of its issuer's name and public key.
+fn request(cert: &TbsCertificate<'_>, issuer: &TbsCertificate<'_>) -> Option<Vec<u8>> {
+    let public_key = public_key(issuer)?;
+
+    let hash_algorithm = [
+        der::write(der::OBJECT_IDENTIFIER, SHA1_OID),
+        der::write(der::NULL, &[]),
+    ]
+    .concat();
+
+    let cert_id = [
+        der::write(der::SEQUENCE, &hash_algorithm),
+        der::write(
+            der::OCTET_STRING,
+            digest(&SHA1_FOR_LEGACY_USE_ONLY, cert.issuer).as_ref(),
+        ),
+        der::write(
+            der::OCTET_STRING,
+            digest(&SHA1_FOR_LEGACY_USE_ONLY, public_key).as_ref(),
+        ),
+        der::write(der::INTEGER, cert.serial_number),
+    ]
+    .concat();
+
+    // The CertID is wrapped in a Request, a list of requests, a
+    // TBSRequest and finally the OCSPRequest.
+    let mut request = der::write(der::SEQUENCE, &cert_id);
+    for _ in 0..4 {
+        request = der::write(der::SEQUENCE, &request);
+    }
+    Some(request)
+}
+
+/// The hash function that a CertID's hashAlgorithm names.
+fn hash_function(oid: &[u8]) -> Option<&'static Algorithm> {
+    match oid {
+        SHA1_OID => Some(&SHA1_FOR_LEGACY_USE_ONLY),
+        SHA256_OID => Some(&SHA256),
+        _ => None,
+    }
+}
+
+/// The bits of a certificate's subject public key, which is what a
+/// CertID's issuerKeyHash is a hash of.
+fn public_key<'a>(cert: &TbsCertificate<'a>) -> Option<&'a [u8]> {
+    let (_algorithm, rest) = der::expect(der::SEQUENCE, cert.subject_public_key_info)?;
+    let (public_key, _) = der::expect(der::BIT_STRING, rest)?;
+    // The first byte of a bit string counts its unused bits.
+    public_key.get(1..)
+}
+
+fn responder_url(cert: &TbsCertificate<'_>) -> Option<String> {
+    let extension = cert.extension(AUTHORITY_INFO_ACCESS_OID)?;
+    let (mut access_descriptions, _) = der::expect(der::SEQUENCE, extension)?;
+
+    while let Some((access_description, rest)) = der::expect(der::SEQUENCE, access_descriptions) {
+        let (method, location) = der::expect(der::OBJECT_IDENTIFIER, access_description)?;
+        if method == OCSP_OID {
+            if let Some((uri, _)) = der::expect(URI, location) {
+                return str::from_utf8(uri).ok().map(String::from);
+            }
+        }
+        access_descriptions = rest;
+    }
+
+    None
+}
+
+/// Responders are reached over plain http, since the responses are
+/// signed and fetching them over https could require OCSP itself.
+/// This speaks http/1.0, which some responders still answer with, so
+/// that the response is never chunked and ends when the connection
+/// closes.
+async fn post(url: &str, body: Vec<u8>) -> io::Result<Vec<u8>> {
+    let url = Url::parse(url).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    if url.scheme() != "http" {
+        return Err(invalid("ocsp responder url must be http"));
+    }
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| invalid("ocsp responder url has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let mut stream = TcpStream::connect((host, port)).await?;
+
+    let head = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/ocsp-request\r\nAccept: application/ocsp-response\r\nContent-Length: {}\r\n\r\n",
+        &url[Position::BeforePath..Position::AfterQuery],
+        &url[Position::BeforeHost..Position::AfterPort],
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+
+    let mut response = Vec::new();
+    (&mut stream)
+        .take(MAX_RESPONSE_LEN)
+        .read_to_end(&mut response)
+        .await?;
+
+    let header_len = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("invalid http response from ocsp responder"))?;
+    let status = str::from_utf8(&response[..header_len])
+        .ok()
+        .and_then(|head| head.split(' ').nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid("invalid http response from ocsp responder"))?;
+
+    if !(200..300).contains(&status) {
+        return Err(io::Error::other(format!(
+            "ocsp responder {} returned {}",
+            url, status
+        )));
+    }
+
+    Ok(response.split_off(header_len + 4))
+}
+
+/// Reads the responseStatus from an `OCSPResponse`.
+fn response_status(der: &[u8]) -> Option<u8> {
+    let (response, _) = der::expect(der::SEQUENCE, der)?;
+    match der::expect(der::ENUMERATED, response)? {
+        ([status], _) => Some(*status),
+        _ => None,
+    }
+}
+
+/// Finds the `SingleResponse` about `cert` in a successful
+/// `OCSPResponse`, returning the tag of its certStatus along with its
+/// thisUpdate and nextUpdate times. Responses are matched by serial
+/// number, and by the hashes of the issuer's name and key when the
+/// issuer is known.
+fn single_response(
+    der: &[u8],
+    cert: &TbsCertificate<'_>,
+    issuer: Option<&TbsCertificate<'_>>,
+) -> Option<(u8, SystemTime, Option<SystemTime>)> {
+    let (response, _) = der::expect(der::SEQUENCE, der)?;
+    let (_status, rest) = der::expect(der::ENUMERATED, response)?;
+    let (response_bytes, _) = der::expect(der::CONTEXT_0, rest)?;
+    let (response_bytes, _) = der::expect(der::SEQUENCE, response_bytes)?;
+    let (response_type, rest) = der::expect(der::OBJECT_IDENTIFIER, response_bytes)?;
+    if response_type != BASIC_RESPONSE_OID {
+        return None;
+    }
+
+    let (basic_response, _) = der::expect(der::OCTET_STRING, rest)?;
+    let (basic_response, _) = der::expect(der::SEQUENCE, basic_response)?;
+    let (response_data, _) = der::expect(der::SEQUENCE, basic_response)?;
+
+    // The version is optional, and the responder id is either a name
+    // or a key hash.
+    let rest = match der::expect(der::CONTEXT_0, response_data) {
+        Some((_version, rest)) => rest,
+        None => response_data,
+    };
+    let (_, _responder_id, rest) = der::read(rest)?;
+    let (_produced_at, rest) = der::expect(der::GENERALIZED_TIME, rest)?;
+    let (mut responses, _) = der::expect(der::SEQUENCE, rest)?;
+
+    while let Some((single_response, rest)) = der::expect(der::SEQUENCE, responses) {
+        let (cert_id, after_cert_id) = der::expect(der::SEQUENCE, single_response)?;
+        let (hash_algorithm, cert_id) = der::expect(der::SEQUENCE, cert_id)?;
+        let (issuer_name_hash, cert_id) = der::expect(der::OCTET_STRING, cert_id)?;
+        let (issuer_key_hash, cert_id) = der::expect(der::OCTET_STRING, cert_id)?;
+        let (serial_number, _) = der::expect(der::INTEGER, cert_id)?;
+
+        let issuer_matches = match issuer {
+            Some(issuer) => issued_by(
+                hash_algorithm,
+                issuer_name_hash,
+                issuer_key_hash,
+                cert,
+                issuer,
+            )
+            .unwrap_or(false),
+            None => true,
+        };
+
+        if serial_number == cert.serial_number && issuer_matches {
+            let (cert_status, _, rest) = der::read(after_cert_id)?;
+            let (this_update, rest) = der::read_time(rest)?;
+            let next_update = match der::expect(der::CONTEXT_0, rest) {
+                Some((next_update, _)) => Some(der::read_time(next_update)?.0),
+                None => None,
+            };
+            return Some((cert_status, this_update, next_update));
+        }
+
+        responses = rest;
+    }
+
+    None
+}
+
+/// Whether the hashes in a CertID are of the name and key of the
+/// certificate's issuer.
+fn issued_by(
+    hash_algorithm: &[u8],
+    issuer_name_hash: &[u8],
+    issuer_key_hash: &[u8],
+    cert: &TbsCertificate<'_>,
+    issuer: &TbsCertificate<'_>,
+) -> Option<bool> {
+    let (oid, _) = der::expect(der::OBJECT_IDENTIFIER, hash_algorithm)?;
+    let algorithm = hash_function(oid)?;
+    Some(
+        digest(algorithm, cert.issuer).as_ref() == issuer_name_hash
+            && digest(algorithm, public_key(issuer)?).as_ref() == issuer_key_hash,
+    )
+}
+
+fn status_name(status: u8) -> &'static str {
+    match status {
+        1 => "malformedRequest",
+        2 => "internalError",
+        3 => "tryLater",
+        5 => "sigRequired",
+        6 => "unauthorized",
+        _ => "unknown",
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::certificates::CertSource;
+
+    use async_std::net::TcpListener;
+    use async_std::task;
+
+    const THIS_UPDATE: &str = "20200101000000Z";
+    const NEXT_UPDATE: &str = "21000101000000Z";
+
+    /// Where the issuer name hash starts in a CertID made with sha-1,
+    /// after the sequence header and the hash algorithm, with the key
+    /// hash 22 bytes later.
+    const NAME_HASH: usize = 2 + 11 + 2;
+
+    fn chain() -> Vec<Certificate> {
+        let pem = [
+            &include_bytes!("../tests/fixtures/ec.pem")[..],
+            include_bytes!("../tests/fixtures/ca.pem"),
+        ]
+        .concat();
+        CertSource::Pem(pem).load().unwrap()
+    }
+
+    fn time(time: &str) -> Vec<u8> {
+        der::write(der::GENERALIZED_TIME, time.as_bytes())
+    }
+
+    /// The CertID that a responder would send back for the first cert
+    /// in `chain`, with its hashes made with `algorithm`.
+    fn cert_id(chain: &[Certificate], algorithm: &'static Algorithm, oid: &[u8]) -> Vec<u8> {
+        let cert = TbsCertificate::parse(&chain[0]).unwrap();
+        let issuer = TbsCertificate::parse(&chain[1]).unwrap();
+        let hash_algorithm = [
+            der::write(der::OBJECT_IDENTIFIER, oid),
+            der::write(der::NULL, &[]),
+        ]
+        .concat();
+        der::write(
+            der::SEQUENCE,
+            &[
+                der::write(der::SEQUENCE, &hash_algorithm),
+                der::write(der::OCTET_STRING, digest(algorithm, cert.issuer).as_ref()),
+                der::write(
+                    der::OCTET_STRING,
+                    digest(algorithm, public_key(&issuer).unwrap()).as_ref(),
+                ),
+                der::write(der::INTEGER, cert.serial_number),
+            ]
+            .concat(),
+        )
+    }
+
+    /// A successful response with a single unsigned `SingleResponse`.
+    fn response(cert_id: &[u8], cert_status: &[u8], next_update: &str) -> Vec<u8> {
+        let single_response = [
+            cert_id,
+            cert_status,
+            &time(THIS_UPDATE),
+            &der::write(der::CONTEXT_0, &time(next_update)),
+        ]
+        .concat();
+
+        let response_data = [
+            // a responder id by key hash
+            der::write(0xa2, &der::write(der::OCTET_STRING, &[0; 20])),
+            time(THIS_UPDATE),
+            der::write(der::SEQUENCE, &der::write(der::SEQUENCE, &single_response)),
+        ]
+        .concat();
+
+        let basic_response = [
+            der::write(der::SEQUENCE, &response_data),
+            der::write(der::SEQUENCE, &[]),
+            der::write(der::BIT_STRING, &[0]),
+        ]
+        .concat();
+
+        let response_bytes = [
+            der::write(der::OBJECT_IDENTIFIER, BASIC_RESPONSE_OID),
+            der::write(
+                der::OCTET_STRING,
+                &der::write(der::SEQUENCE, &basic_response),
+            ),
+        ]
+        .concat();
+
+        der::write(
+            der::SEQUENCE,
+            &[
+                der::write(der::ENUMERATED, &[0]),
+                der::write(der::CONTEXT_0, &der::write(der::SEQUENCE, &response_bytes)),
+            ]
+            .concat(),
+        )
+    }
+
+    fn good() -> Vec<u8> {
+        der::write(0x80, &[])
+    }
+
+    fn error(result: io::Result<OcspResponse>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    /// Answers a single request as a responder would, with a response
+    /// built from the CertID in the request.
+    async fn responder(respond: impl FnOnce(&[u8]) -> Vec<u8> + Send + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ocsp", listener.local_addr().unwrap());
+
+        task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            let body = loop {
+                let len = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..len]);
+                let head = match request.windows(4).position(|window| window == b"\r\n\r\n") {
+                    Some(end) => end + 4,
+                    None => continue,
+                };
+                let content_length: usize = str::from_utf8(&request[..head])
+                    .unwrap()
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                if request.len() == head + content_length {
+                    break request.split_off(head);
+                }
+            };
+
+            let mut cert_id = &body[..];
+            for _ in 0..4 {
+                cert_id = der::expect(der::SEQUENCE, cert_id).unwrap().0;
+            }
+            let (cert_id, _) = der::expect_encoded(der::SEQUENCE, cert_id).unwrap();
+
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: application/ocsp-response\r\n\r\n")
+                .await
+                .unwrap();
+            stream.write_all(&respond(cert_id)).await.unwrap();
+        });
+
+        url
+    }
+
+    #[test]
+    fn fetch_from_responder() {
+        task::block_on(async {
+            let url = responder(|cert_id| response(cert_id, &good(), NEXT_UPDATE)).await;
+            let response = fetch(&chain(), Some(&url)).await.unwrap();
+            assert_eq!(
+                response.next_update.map(der::format_time).unwrap(),
+                "2100-01-01 00:00:00 UTC"
+            );
+        });
+    }
+
+    #[test]
+    fn fetch_response_for_another_issuer() {
+        for (offset, what) in [(NAME_HASH, "name"), (NAME_HASH + 22, "key")] {
+            task::block_on(async {
+                let url = responder(move |cert_id| {
+                    let mut cert_id = cert_id.to_vec();
+                    cert_id[offset] ^= 0xff;
+                    response(&cert_id, &good(), NEXT_UPDATE)
+                })
+                .await;
+                assert_eq!(
+                    error(fetch(&chain(), Some(&url)).await),
+                    "invalid ocsp response, or not for this certificate",
+                    "{} hash",
+                    what
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn fetch_unsuccessful_response() {
+        task::block_on(async {
+            let url =
+                responder(|_| der::write(der::SEQUENCE, &der::write(der::ENUMERATED, &[3]))).await;
+            assert_eq!(
+                error(fetch(&chain(), Some(&url)).await),
+                "ocsp response status is tryLater"
+            );
+        });
+    }
+
+    #[test]
+    fn sha256_cert_id() {
+        let chain = chain();
+        let cert_id = cert_id(&chain, &SHA256, SHA256_OID);
+        assert!(OcspResponse::parse(response(&cert_id, &good(), NEXT_UPDATE), &chain).is_ok());
+    }
+
+    #[test]
+    fn unknown_hash_algorithm() {
+        let chain = chain();
+        // md5, which no responder should use
+        let cert_id = cert_id(
+            &chain,
+            &SHA256,
+            &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x05],
+        );
+        assert_eq!(
+            error(OcspResponse::parse(
+                response(&cert_id, &good(), NEXT_UPDATE),
+                &chain
+            )),
+            "invalid ocsp response, or not for this certificate"
+        );
+    }
+
+    #[test]
+    fn without_the_issuer_only_the_serial_number_is_compared() {
+        let chain = chain();
+        let mut cert_id = cert_id(&chain, &SHA1_FOR_LEGACY_USE_ONLY, SHA1_OID);
+        cert_id[NAME_HASH] ^= 0xff;
+        let response = response(&cert_id, &good(), NEXT_UPDATE);
+
+        assert!(OcspResponse::parse(response.clone(), &chain).is_err());
+        assert!(OcspResponse::parse(response, &chain[..1]).is_ok());
+    }
+
+    #[test]
+    fn other_serial_number() {
+        let chain = chain();
+        // the ca is self-signed, so its CertID has the same issuer
+        // hashes but its own serial number
+        let ca = [chain[1].clone(), chain[1].clone()];
+        let cert_id = cert_id(&ca, &SHA1_FOR_LEGACY_USE_ONLY, SHA1_OID);
+        assert!(OcspResponse::parse(response(&cert_id, &good(), NEXT_UPDATE), &chain).is_err());
+    }
+
+    #[test]
+    fn revoked() {
+        let chain = chain();
+        let cert_id = cert_id(&chain, &SHA1_FOR_LEGACY_USE_ONLY, SHA1_OID);
+        let revoked = der::write(REVOKED, &time("20240101000000Z"));
+        let der = response(&cert_id, &revoked, NEXT_UPDATE);
+
+        let cert = TbsCertificate::parse(&chain[0]).unwrap();
+        let issuer = TbsCertificate::parse(&chain[1]).unwrap();
+        let (cert_status, _, _) = single_response(&der, &cert, Some(&issuer)).unwrap();
+        assert_eq!(cert_status, REVOKED);
+
+        // a revoked response is still stapled, so that clients see it
+        assert!(OcspResponse::parse(der, &chain).is_ok());
+    }
+
+    #[test]
+    fn expired() {
+        let chain = chain();
+        let cert_id = cert_id(&chain, &SHA1_FOR_LEGACY_USE_ONLY, SHA1_OID);
+        assert_eq!(
+            error(OcspResponse::parse(
+                response(&cert_id, &good(), "20210101000000Z"),
+                &chain
+            )),
+            "ocsp response has expired"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use async_std::io;
+use async_std::net::TcpStream;
+
+/// The first byte of every TLS handshake record.
+const TLS_HANDSHAKE: u8 = 0x16;
+
+/// What a [`TlsListener`](crate::TlsListener) does with clients that
+/// connect to it with plaintext http instead of TLS. Without this,
+/// those clients see a failed TLS handshake.
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::{PlaintextHttp, TlsListener};
+/// let listener = TlsListener::<()>::build()
+///     .addrs("localhost:4433")
+///     .cert("./tls/localhost-4433.cert")
+///     .key("./tls/localhost-4433.key")
+///     .plaintext_http(PlaintextHttp::Redirect)
+///     .finish();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaintextHttp {
+    /// Answer every request with a redirect to the same host and path
+    /// over https on this listener's port, in the same way as
+    /// [`TlsListenerBuilder::http_redirect`](crate::TlsListenerBuilder::http_redirect).
+    Redirect,
+
+    /// Serve requests with the tide app as usual, but without any of
+    /// the protections of TLS. These requests have an `http` url and
+    /// no [`TlsConnectionInfo`](crate::TlsConnectionInfo).
+    Serve,
+}
+
+/// Peeks at the first byte the client sent, without consuming it, to
+/// tell whether it is beginning a TLS handshake.
+pub(crate) async fn is_tls(stream: &TcpStream) -> io::Result<bool> {
+    let mut first = [0; 1];
+    match stream.peek(&mut first).await? {
+        0 => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before handshake",
+        )),
+        _ => Ok(first[0] == TLS_HANDSHAKE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use futures_lite::{AsyncReadExt, AsyncWriteExt};
+
+    /// Connects to a local listener, sends `first_bytes` and then
+    /// closes the client side, returning the server side.
+    async fn connection(first_bytes: &'static [u8]) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(first_bytes).await.unwrap();
+        drop(client);
+        listener.accept().await.unwrap().0
+    }
+
+    #[test]
+    fn tls_handshake_is_tls() {
+        task::block_on(async {
+            // a tls 1.0 handshake record header, as sent in a ClientHello
+            let stream = connection(&[TLS_HANDSHAKE, 0x03, 0x01, 0x00, 0xf4]).await;
+            assert!(is_tls(&stream).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn request_line_is_plaintext_and_left_unread() {
+        task::block_on(async {
+            let mut stream = connection(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+            assert!(!is_tls(&stream).await.unwrap());
+
+            let mut received = String::new();
+            stream.read_to_string(&mut received).await.unwrap();
+            assert_eq!(received, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        });
+    }
+
+    #[test]
+    fn closed_before_first_byte() {
+        task::block_on(async {
+            let stream = connection(b"").await;
+            let err = is_tls(&stream).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        });
+    }
+}
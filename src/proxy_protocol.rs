@@ -0,0 +1,477 @@
+use async_std::io::{self, ReadExt};
+use async_std::net::TcpStream;
+use async_std::task;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+use std::time::{Duration, Instant};
+
+/// The start of a version 1 (text) header.
+const V1_PREFIX: &[u8] = b"PROXY ";
+
+/// The longest a version 1 header may be, including the trailing crlf.
+const V1_MAX_LEN: usize = 107;
+
+/// The start of a version 2 (binary) header.
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// How a [`TlsListener`](crate::TlsListener) treats the
+/// [PROXY protocol](https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt)
+/// header that a load balancer sends ahead of each tcp connection it
+/// forwards. Both the version 1 (text) and version 2 (binary) formats
+/// are understood. The source address in the header is used as the
+/// peer address of each request on the connection.
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::{ProxyProtocol, TlsListener};
+/// let listener = TlsListener::<()>::build()
+///     .addrs("0.0.0.0:443")
+///     .cert("./tls/example.com.cert")
+///     .key("./tls/example.com.key")
+///     .proxy_protocol(ProxyProtocol::Required)
+///     .finish();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    /// Close any connection that does not begin with a PROXY protocol
+    /// header. Use this when every connection arrives through a load
+    /// balancer.
+    Required,
+
+    /// Read a PROXY protocol header if a connection begins with one,
+    /// and otherwise use the tcp peer address. Any client that can
+    /// reach the listener directly can claim an arbitrary address
+    /// with this mode.
+    Optional,
+}
+
+impl ProxyProtocol {
+    /// Reads the header off the front of `stream`, leaving the stream
+    /// positioned at the start of the TLS handshake. This returns the
+    /// source address the header conveys, or `None` if the header is
+    /// absent in optional mode or does not describe a tcp connection,
+    /// such as a load balancer health check.
+    pub(crate) async fn read_header(self, stream: &TcpStream) -> io::Result<Option<SocketAddr>> {
+        match (detect(stream, self).await?, self) {
+            (Some(Version::V1), _) => read_v1(stream).await,
+            (Some(Version::V2), _) => read_v2(stream).await,
+            (None, Self::Optional) => Ok(None),
+            (None, Self::Required) => Err(invalid("missing proxy protocol header")),
+        }
+    }
+}
+
+enum Version {
+    V1,
+    V2,
+}
+
+/// How long an optional header's signature may take to arrive once the
+/// connection has begun with part of it.
+const SIGNATURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Consumes the header signature from the start of the stream if it
+/// begins with one. When the header is optional, nothing is consumed
+/// unless the whole signature is there, since anything else belongs to
+/// the TLS handshake or a plaintext request.
+async fn detect(mut stream: &TcpStream, mode: ProxyProtocol) -> io::Result<Option<Version>> {
+    let mut first = [0; 1];
+    if stream.peek(&mut first).await? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before proxy protocol header",
+        ));
+    }
+
+    // A TLS handshake begins with neither of these.
+    let (version, signature) = match first[0] {
+        b'P' => (Version::V1, V1_PREFIX),
+        b'\r' => (Version::V2, V2_SIGNATURE),
+        _ => return Ok(None),
+    };
+
+    let mut buf = [0; 12];
+    let buf = &mut buf[..signature.len()];
+
+    if mode == ProxyProtocol::Optional {
+        // A peek returns as soon as anything is buffered, so the rest of
+        // a signature that arrives in pieces can only be waited for by
+        // peeking again, which is bounded here rather than left to the
+        // handshake timeout.
+        let deadline = Instant::now() + SIGNATURE_TIMEOUT;
+        loop {
+            let len = stream.peek(buf).await?;
+            if buf[..len] != signature[..len] {
+                return Ok(None);
+            } else if len == signature.len() {
+                break;
+            } else if len == 0 || Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "incomplete proxy protocol signature",
+                ));
+            }
+            task::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    stream.read_exact(buf).await?;
+    Ok(if buf == signature {
+        Some(version)
+    } else {
+        None
+    })
+}
+
+/// Reads the rest of a header such as
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`.
+async fn read_v1(mut stream: &TcpStream) -> io::Result<Option<SocketAddr>> {
+    // Only as far as the line feed is consumed, so as not to take any
+    // of the TLS handshake that follows the header.
+    let max_len = V1_MAX_LEN - V1_PREFIX.len();
+    let mut line = Vec::with_capacity(max_len);
+    let mut buf = [0; V1_MAX_LEN];
+    while !line.ends_with(b"\n") {
+        let limit = max_len - line.len();
+        if limit == 0 {
+            return Err(invalid("proxy protocol v1 header is too long"));
+        }
+
+        let peeked = stream.peek(&mut buf[..limit]).await?;
+        if peeked == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed during proxy protocol header",
+            ));
+        }
+
+        let len = buf[..peeked]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(peeked, |end| end + 1);
+        stream.read_exact(&mut buf[..len]).await?;
+        line.extend_from_slice(&buf[..len]);
+    }
+
+    let line = line
+        .strip_suffix(b"\r\n")
+        .ok_or_else(|| invalid("invalid proxy protocol v1 header"))?;
+    let line =
+        str::from_utf8(line).map_err(|_| invalid("proxy protocol v1 header is not ascii"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+
+    match fields.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+
+        // The fields after the protocol are the source address, the
+        // destination address, the source port and the destination port.
+        [protocol @ ("TCP4" | "TCP6"), ip, _, port, _] => {
+            let ip: IpAddr = ip
+                .parse()
+                .map_err(|_| invalid("invalid proxy protocol v1 source address"))?;
+            let port: u16 = port
+                .parse()
+                .map_err(|_| invalid("invalid proxy protocol v1 source port"))?;
+
+            if ip.is_ipv4() != (*protocol == "TCP4") {
+                return Err(invalid("proxy protocol v1 address does not match protocol"));
+            }
+
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+
+        _ => Err(invalid("invalid proxy protocol v1 header")),
+    }
+}
+
+/// Reads the rest of a binary header: a version and command byte, an
+/// address family and transport byte, a two byte length, and then that
+/// many bytes of addresses and optional TLVs.
+async fn read_v2(mut stream: &TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+
+    let version = header[0] >> 4;
+    let command = header[0] & 0x0f;
+    let family = header[1] >> 4;
+    let transport = header[1] & 0x0f;
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+
+    if version != 2 {
+        return Err(invalid("unsupported proxy protocol version"));
+    }
+
+    let mut addresses = vec![0; len];
+    stream.read_exact(&mut addresses).await?;
+
+    match (command, family, transport) {
+        // LOCAL, sent by the load balancer on its own behalf
+        (0x0, _, _) => Ok(None),
+
+        // PROXY over AF_INET and STREAM
+        (0x1, 0x1, 0x1) if len >= 12 => {
+            let ip = Ipv4Addr::from([addresses[0], addresses[1], addresses[2], addresses[3]]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+
+        // PROXY over AF_INET6 and STREAM
+        (0x1, 0x2, 0x1) if len >= 36 => {
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+
+        // PROXY over AF_UNIX or AF_UNSPEC, or over DGRAM or UNSPEC
+        // transports, none of which carry a tcp address
+        (0x1, 0x0 | 0x3, 0x0..=0x2) | (0x1, 0x1 | 0x2, 0x0 | 0x2) => Ok(None),
+
+        _ => Err(invalid("invalid proxy protocol v2 header")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::io::WriteExt;
+    use async_std::net::{Shutdown, TcpListener};
+
+    /// Sends `pieces` a short while apart over a local connection, and
+    /// returns the header read from the other end along with whatever
+    /// was left on the stream after it.
+    fn read_header(
+        mode: ProxyProtocol,
+        pieces: &[&[u8]],
+    ) -> (io::Result<Option<SocketAddr>>, Vec<u8>) {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (mut server, _) = listener.accept().await.unwrap();
+
+            let pieces: Vec<Vec<u8>> = pieces.iter().map(|piece| piece.to_vec()).collect();
+            let sender = task::spawn(async move {
+                for piece in pieces {
+                    client.write_all(&piece).await.unwrap();
+                    task::sleep(Duration::from_millis(50)).await;
+                }
+                client.shutdown(Shutdown::Write).unwrap();
+            });
+
+            let header = mode.read_header(&server).await;
+            let mut rest = vec![];
+            server.read_to_end(&mut rest).await.unwrap();
+            sender.await;
+            (header, rest)
+        })
+    }
+
+    fn error(result: io::Result<Option<SocketAddr>>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    fn v2(command: u8, family_and_transport: u8, addresses: &[u8]) -> Vec<u8> {
+        let len = (addresses.len() as u16).to_be_bytes();
+        [
+            V2_SIGNATURE,
+            &[0x20 | command, family_and_transport, len[0], len[1]],
+            addresses,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let (header, rest) = read_header(
+            ProxyProtocol::Required,
+            &[b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n\x16handshake"],
+        );
+        assert_eq!(header.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"\x16handshake");
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let (header, rest) = read_header(
+            ProxyProtocol::Optional,
+            &[b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n\x16"],
+        );
+        assert_eq!(
+            header.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert_eq!(rest, b"\x16");
+    }
+
+    #[test]
+    fn v1_in_pieces() {
+        let (header, rest) = read_header(
+            ProxyProtocol::Optional,
+            &[
+                b"PRO",
+                b"XY TCP4 192.0.2.1 ",
+                b"198.51.100.1 56324 443\r",
+                b"\n\x16handshake",
+            ],
+        );
+        assert_eq!(header.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"\x16handshake");
+    }
+
+    #[test]
+    fn v1_unknown() {
+        for header in [&b"PROXY UNKNOWN\r\n"[..], b"PROXY UNKNOWN ::1 ::1 1 2\r\n"] {
+            let (header, rest) = read_header(ProxyProtocol::Required, &[header, b"\x16"]);
+            assert_eq!(header.unwrap(), None);
+            assert_eq!(rest, b"\x16");
+        }
+    }
+
+    #[test]
+    fn v1_truncated() {
+        let (header, _) = read_header(ProxyProtocol::Required, &[b"PROXY TCP4 192.0.2.1"]);
+        assert_eq!(
+            error(header),
+            "connection closed during proxy protocol header"
+        );
+
+        let (header, _) = read_header(ProxyProtocol::Optional, &[b"PROX"]);
+        assert_eq!(error(header), "incomplete proxy protocol signature");
+    }
+
+    #[test]
+    fn v1_too_long() {
+        let header = format!("PROXY UNKNOWN {}\r\n", "a".repeat(100));
+        let (header, _) = read_header(ProxyProtocol::Required, &[header.as_bytes()]);
+        assert_eq!(error(header), "proxy protocol v1 header is too long");
+
+        let longest = format!("PROXY UNKNOWN {}\r\n", "a".repeat(91));
+        assert_eq!(longest.len(), V1_MAX_LEN);
+        let (header, _) = read_header(ProxyProtocol::Required, &[longest.as_bytes()]);
+        assert_eq!(header.unwrap(), None);
+    }
+
+    #[test]
+    fn v1_invalid() {
+        for (header, message) in [
+            (
+                &b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n"[..],
+                "proxy protocol v1 address does not match protocol",
+            ),
+            (
+                b"PROXY TCP4 192.0.2.1 198.51.100.1 70000 443\r\n",
+                "invalid proxy protocol v1 source port",
+            ),
+            (
+                b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+                "invalid proxy protocol v1 header",
+            ),
+            (
+                b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\n",
+                "invalid proxy protocol v1 header",
+            ),
+        ] {
+            let (header, _) = read_header(ProxyProtocol::Required, &[header]);
+            assert_eq!(error(header), message);
+        }
+    }
+
+    #[test]
+    fn v2_tcp4() {
+        let header = v2(
+            0x1,
+            0x11,
+            &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb],
+        );
+        let (header, rest) = read_header(ProxyProtocol::Required, &[&header, b"\x16"]);
+        assert_eq!(header.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"\x16");
+    }
+
+    #[test]
+    fn v2_tcp6_with_tlvs() {
+        let mut addresses = vec![0; 36];
+        addresses[..16].copy_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        addresses[32..34].copy_from_slice(&56324u16.to_be_bytes());
+        // a PP2_TYPE_NOOP tlv
+        addresses.extend_from_slice(&[0x04, 0x00, 0x02, 0x00, 0x00]);
+
+        let header = v2(0x1, 0x21, &addresses);
+        let (header, rest) = read_header(
+            ProxyProtocol::Optional,
+            &[&header[..5], &header[5..], b"\x16"],
+        );
+        assert_eq!(
+            header.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert_eq!(rest, b"\x16");
+    }
+
+    #[test]
+    fn v2_without_a_tcp_address() {
+        let inet = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        for header in [
+            // LOCAL
+            v2(0x0, 0x00, &[]),
+            v2(0x0, 0x11, &inet),
+            // AF_UNSPEC
+            v2(0x1, 0x00, &[]),
+            // AF_INET over DGRAM
+            v2(0x1, 0x12, &inet),
+            // AF_UNIX over STREAM
+            v2(0x1, 0x31, &[0; 216]),
+        ] {
+            let (header, rest) = read_header(ProxyProtocol::Required, &[&header, b"\x16"]);
+            assert_eq!(header.unwrap(), None);
+            assert_eq!(rest, b"\x16");
+        }
+    }
+
+    #[test]
+    fn v2_invalid() {
+        let header = v2(0x1, 0x11, &[192, 0, 2, 1]);
+        let (header, _) = read_header(ProxyProtocol::Required, &[&header]);
+        assert_eq!(error(header), "invalid proxy protocol v2 header");
+
+        let mut header = v2(0x1, 0x11, &[]);
+        header[12] = 0x11;
+        let (header, _) = read_header(ProxyProtocol::Required, &[&header]);
+        assert_eq!(error(header), "unsupported proxy protocol version");
+
+        let header = v2(
+            0x1,
+            0x11,
+            &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb],
+        );
+        let (header, _) = read_header(ProxyProtocol::Required, &[&header[..20]]);
+        assert_eq!(header.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn missing_header() {
+        for start in [&b"\x16\x03\x01"[..], b"POST / HTTP/1.1\r\n"] {
+            let (header, rest) = read_header(ProxyProtocol::Optional, &[start]);
+            assert_eq!(header.unwrap(), None);
+            assert_eq!(rest, start);
+
+            let (header, _) = read_header(ProxyProtocol::Required, &[start]);
+            assert_eq!(error(header), "missing proxy protocol header");
+        }
+
+        let (header, _) = read_header(ProxyProtocol::Optional, &[]);
+        assert_eq!(
+            error(header),
+            "connection closed before proxy protocol header"
+        );
+    }
+}
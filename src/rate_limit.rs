@@ -0,0 +1,480 @@
+use async_std::io;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many events a single client ip address may cause within a
+/// period, for
+/// [`TlsListenerBuilder::connection_rate_limit`](crate::TlsListenerBuilder::connection_rate_limit)
+/// and
+/// [`TlsListenerBuilder::failed_handshake_rate_limit`](crate::TlsListenerBuilder::failed_handshake_rate_limit).
+///
+/// This is a token bucket: an address may use up to `count` at once,
+/// and regains them evenly over `period`, so `RateLimit::new(20,
+/// Duration::from_secs(10))` allows a burst of 20 followed by one
+/// more every half second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    count: u32,
+    period: Duration,
+}
+
+impl RateLimit {
+    /// Allows `count` events per `period`.
+    pub fn new(count: u32, period: Duration) -> Self {
+        Self { count, period }
+    }
+
+    /// The number of events that may happen at once.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// How long it takes to regain all of them.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn per_second(&self) -> f64 {
+        f64::from(self.count) / self.period.as_secs_f64()
+    }
+}
+
+/// What a [`TlsListener`](crate::TlsListener) does with new tcp
+/// connections from an ip address that has exceeded
+/// [`TlsListenerBuilder::connection_rate_limit`](crate::TlsListenerBuilder::connection_rate_limit)
+/// or
+/// [`TlsListenerBuilder::failed_handshake_rate_limit`](crate::TlsListenerBuilder::failed_handshake_rate_limit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitBehavior {
+    /// Close new connections immediately without attempting a TLS
+    /// handshake. This is the default.
+    #[default]
+    Close,
+
+    /// Hold new connections without reading from them until the
+    /// address is within its limits again, then serve them. Connections
+    /// that would have to wait longer than the limit's period are
+    /// closed instead, so that a persistent client cannot build an
+    /// unbounded queue. Held connections only take a place under
+    /// [`TlsListenerBuilder::max_connections`](crate::TlsListenerBuilder::max_connections)
+    /// once they are served.
+    Delay,
+}
+
+/// Whether to serve a newly accepted connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Admission {
+    Accept,
+    Delay(Duration),
+    Close,
+}
+
+/// Applies the rate limits in the accept loop, keeping a pair of token
+/// buckets for each ip address that has connected recently. Clones
+/// share the same buckets.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RateLimiter {
+    pub(crate) connections: Option<RateLimit>,
+    pub(crate) failed_handshakes: Option<RateLimit>,
+    pub(crate) behavior: RateLimitBehavior,
+    peers: Arc<Mutex<Peers>>,
+}
+
+#[derive(Debug, Default)]
+struct Peers {
+    buckets: HashMap<IpAddr, PeerBuckets>,
+    prune_at: usize,
+}
+
+#[derive(Debug)]
+struct PeerBuckets {
+    connections: Bucket,
+    failed_handshakes: Bucket,
+    limited: bool,
+}
+
+/// Addresses are forgotten once their buckets are full again, which is
+/// checked whenever the number of addresses doubles.
+const MIN_PRUNE_AT: usize = 1024;
+
+impl RateLimiter {
+    fn is_enabled(&self) -> bool {
+        self.connections.is_some() || self.failed_handshakes.is_some()
+    }
+
+    pub(crate) fn check(&self) -> io::Result<()> {
+        for limit in self.connections.iter().chain(&self.failed_handshakes) {
+            if limit.count == 0 || limit.period.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "rate limits require a nonzero count and period",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Decides whether to serve a connection from `ip`, taking a token
+    /// from its connection bucket if so.
+    pub(crate) fn admit(&self, ip: IpAddr) -> Admission {
+        self.admit_at(ip, Instant::now())
+    }
+
+    fn admit_at(&self, ip: IpAddr, now: Instant) -> Admission {
+        if !self.is_enabled() {
+            return Admission::Accept;
+        }
+
+        let mut peers = self.peers.lock().unwrap();
+        let peer = peers.get(ip, now);
+
+        let mut delay = Duration::ZERO;
+        let mut reason = None;
+
+        if let Some(limit) = &self.failed_handshakes {
+            peer.failed_handshakes.refill(limit, now);
+            match peer.failed_handshakes.wait(limit, self.behavior) {
+                Some(wait) if wait.is_zero() => {}
+                Some(wait) => {
+                    delay = wait;
+                    reason = Some("failed handshakes");
+                }
+                None => return peer.limit(ip, "failed handshakes", Admission::Close),
+            }
+        }
+
+        if let Some(limit) = &self.connections {
+            peer.connections.refill(limit, now);
+            match peer.connections.wait(limit, self.behavior) {
+                Some(wait) => {
+                    if !wait.is_zero() {
+                        reason = Some("connections");
+                    }
+                    delay = delay.max(wait);
+                    peer.connections.take(limit, f64::NEG_INFINITY);
+                }
+                None => return peer.limit(ip, "connections", Admission::Close),
+            }
+        }
+
+        match reason {
+            Some(reason) => peer.limit(ip, reason, Admission::Delay(delay)),
+            None => {
+                peer.limited = false;
+                Admission::Accept
+            }
+        }
+    }
+
+    /// Takes a token from the failed handshake bucket for `ip`.
+    pub(crate) fn record_failure(&self, ip: IpAddr) {
+        self.record_failure_at(ip, Instant::now())
+    }
+
+    fn record_failure_at(&self, ip: IpAddr, now: Instant) {
+        if let Some(limit) = &self.failed_handshakes {
+            let mut peers = self.peers.lock().unwrap();
+            let bucket = &mut peers.get(ip, now).failed_handshakes;
+            bucket.refill(limit, now);
+            // a full bucket of debt is enough to hold off new
+            // connections for a whole period
+            bucket.take(limit, -f64::from(limit.count));
+        }
+    }
+}
+
+impl Peers {
+    fn get(&mut self, ip: IpAddr, now: Instant) -> &mut PeerBuckets {
+        if self.buckets.len() >= self.prune_at.max(MIN_PRUNE_AT) {
+            // an address whose buckets have refilled is no different
+            // from one that has never connected
+            self.buckets.retain(|_, peer| {
+                peer.connections.updated + peer.connections.full_after > now
+                    || peer.failed_handshakes.updated + peer.failed_handshakes.full_after > now
+            });
+            self.prune_at = self.buckets.len() * 2;
+        }
+
+        self.buckets.entry(ip).or_insert_with(|| PeerBuckets {
+            connections: Bucket::full(now),
+            failed_handshakes: Bucket::full(now),
+            limited: false,
+        })
+    }
+}
+
+impl PeerBuckets {
+    /// Logs the first connection from each address to be limited,
+    /// rather than every one of them.
+    fn limit(&mut self, ip: IpAddr, reason: &str, admission: Admission) -> Admission {
+        if !self.limited {
+            self.limited = true;
+            tide::log::warn!("rate limiting connections", {
+                peer_ip: ip.to_string(),
+                reason: reason,
+                behavior: if admission == Admission::Close { "close" } else { "delay" },
+            });
+        }
+        admission
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Starts out infinite, so that the first refill fills the bucket
+    /// to the limit.
+    tokens: f64,
+    updated: Instant,
+    /// How long after `updated` the bucket will be full.
+    full_after: Duration,
+}
+
+impl Bucket {
+    fn full(now: Instant) -> Self {
+        Self {
+            tokens: f64::INFINITY,
+            updated: now,
+            full_after: Duration::ZERO,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        self.set_tokens(limit, self.tokens + elapsed * limit.per_second());
+    }
+
+    /// Takes a token, leaving no less than `floor`.
+    fn take(&mut self, limit: &RateLimit, floor: f64) {
+        self.set_tokens(limit, (self.tokens - 1.0).max(floor));
+    }
+
+    fn set_tokens(&mut self, limit: &RateLimit, tokens: f64) {
+        let capacity = f64::from(limit.count);
+        self.tokens = tokens.min(capacity);
+        self.full_after = Duration::from_secs_f64((capacity - self.tokens) / limit.per_second());
+    }
+
+    /// How long until a token is available, or `None` if the
+    /// connection should be closed.
+    fn wait(&self, limit: &RateLimit, behavior: RateLimitBehavior) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            return Some(Duration::ZERO);
+        }
+
+        let wait = Duration::from_secs_f64((1.0 - self.tokens) / limit.per_second());
+        match behavior {
+            RateLimitBehavior::Delay if wait <= limit.period => Some(wait),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn limiter(
+        connections: Option<RateLimit>,
+        failed_handshakes: Option<RateLimit>,
+        behavior: RateLimitBehavior,
+    ) -> RateLimiter {
+        RateLimiter {
+            connections,
+            failed_handshakes,
+            behavior,
+            ..RateLimiter::default()
+        }
+    }
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    fn assert_delay(admission: Admission, expected: Duration) {
+        match admission {
+            Admission::Delay(delay) => {
+                let difference = delay.max(expected) - delay.min(expected);
+                assert!(difference < secs(0.001), "{:?} != {:?}", delay, expected);
+            }
+            other => panic!("expected a delay of {:?}, got {:?}", expected, other),
+        }
+    }
+
+    #[test]
+    fn disabled_accepts_everything() {
+        let limiter = RateLimiter::default();
+        limiter.check().unwrap();
+        let now = Instant::now();
+        for _ in 0..10_000 {
+            assert_eq!(limiter.admit_at(IP, now), Admission::Accept);
+            limiter.record_failure_at(IP, now);
+        }
+        assert!(limiter.peers.lock().unwrap().buckets.is_empty());
+    }
+
+    #[test]
+    fn check_rejects_empty_limits() {
+        for limit in [
+            RateLimit::new(0, secs(1.0)),
+            RateLimit::new(1, Duration::ZERO),
+        ] {
+            let error = limiter(Some(limit), None, RateLimitBehavior::Close)
+                .check()
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            let error = limiter(None, Some(limit), RateLimitBehavior::Close)
+                .check()
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn burst_then_close() {
+        let limiter = limiter(
+            Some(RateLimit::new(3, secs(3.0))),
+            None,
+            RateLimitBehavior::Close,
+        );
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.admit_at(IP, now), Admission::Accept);
+        }
+        assert_eq!(limiter.admit_at(IP, now), Admission::Close);
+        assert_eq!(limiter.admit_at(IP, now), Admission::Close);
+
+        // other addresses have their own buckets
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(limiter.admit_at(other, now), Admission::Accept);
+    }
+
+    #[test]
+    fn tokens_refill_evenly() {
+        let limiter = limiter(
+            Some(RateLimit::new(2, secs(2.0))),
+            None,
+            RateLimitBehavior::Close,
+        );
+        let start = Instant::now();
+        assert_eq!(limiter.admit_at(IP, start), Admission::Accept);
+        assert_eq!(limiter.admit_at(IP, start), Admission::Accept);
+        assert_eq!(limiter.admit_at(IP, start), Admission::Close);
+
+        assert_eq!(limiter.admit_at(IP, start + secs(0.5)), Admission::Close);
+        assert_eq!(limiter.admit_at(IP, start + secs(1.0)), Admission::Accept);
+        assert_eq!(limiter.admit_at(IP, start + secs(1.0)), Admission::Close);
+
+        // refilling stops at the limit
+        let later = start + secs(60.0);
+        assert_eq!(limiter.admit_at(IP, later), Admission::Accept);
+        assert_eq!(limiter.admit_at(IP, later), Admission::Accept);
+        assert_eq!(limiter.admit_at(IP, later), Admission::Close);
+    }
+
+    #[test]
+    fn delay_until_a_token_is_available() {
+        let limiter = limiter(
+            Some(RateLimit::new(2, secs(2.0))),
+            None,
+            RateLimitBehavior::Delay,
+        );
+        let now = Instant::now();
+        assert_eq!(limiter.admit_at(IP, now), Admission::Accept);
+        assert_eq!(limiter.admit_at(IP, now), Admission::Accept);
+
+        // each delayed connection reserves the next token
+        assert_delay(limiter.admit_at(IP, now), secs(1.0));
+        assert_delay(limiter.admit_at(IP, now), secs(2.0));
+
+        // but none may wait longer than the period
+        assert_eq!(limiter.admit_at(IP, now), Admission::Close);
+        assert_delay(limiter.admit_at(IP, now + secs(1.0)), secs(2.0));
+    }
+
+    #[test]
+    fn failures_run_up_a_debt() {
+        let limiter = limiter(
+            None,
+            Some(RateLimit::new(2, secs(2.0))),
+            RateLimitBehavior::Close,
+        );
+        let start = Instant::now();
+        assert_eq!(limiter.admit_at(IP, start), Admission::Accept);
+        limiter.record_failure_at(IP, start);
+        assert_eq!(limiter.admit_at(IP, start), Admission::Accept);
+        limiter.record_failure_at(IP, start);
+        assert_eq!(limiter.admit_at(IP, start), Admission::Close);
+
+        // further failures take the bucket below zero, but no further
+        // than a full period's worth
+        for _ in 0..10 {
+            limiter.record_failure_at(IP, start);
+        }
+        assert_eq!(limiter.admit_at(IP, start + secs(2.5)), Admission::Close);
+        assert_eq!(limiter.admit_at(IP, start + secs(3.0)), Admission::Accept);
+
+        // admitting a connection does not spend a failure token
+        for _ in 0..10 {
+            assert_eq!(limiter.admit_at(IP, start + secs(60.0)), Admission::Accept);
+        }
+    }
+
+    #[test]
+    fn failures_delay_connections() {
+        let limiter = limiter(
+            Some(RateLimit::new(10, secs(1.0))),
+            Some(RateLimit::new(1, secs(4.0))),
+            RateLimitBehavior::Delay,
+        );
+        let start = Instant::now();
+        assert_eq!(limiter.admit_at(IP, start), Admission::Accept);
+        limiter.record_failure_at(IP, start);
+        assert_delay(limiter.admit_at(IP, start), secs(4.0));
+
+        limiter.record_failure_at(IP, start);
+        assert_eq!(limiter.admit_at(IP, start), Admission::Close);
+        assert_delay(limiter.admit_at(IP, start + secs(4.0)), secs(4.0));
+    }
+
+    #[test]
+    fn full_buckets_are_pruned() {
+        let limiter = limiter(
+            Some(RateLimit::new(2, secs(1.0))),
+            None,
+            RateLimitBehavior::Close,
+        );
+        let start = Instant::now();
+        let ip = |i: usize| IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + i as u32));
+        let buckets = || limiter.peers.lock().unwrap().buckets.len();
+
+        for i in 0..MIN_PRUNE_AT {
+            assert_eq!(limiter.admit_at(ip(i), start), Admission::Accept);
+        }
+        assert_eq!(buckets(), MIN_PRUNE_AT);
+
+        // addresses whose buckets are still refilling are kept, and the
+        // next check waits until there are twice as many
+        assert_eq!(limiter.admit_at(IP, start + secs(0.25)), Admission::Accept);
+        assert_eq!(buckets(), MIN_PRUNE_AT + 1);
+        assert_eq!(limiter.peers.lock().unwrap().prune_at, 2 * MIN_PRUNE_AT);
+
+        for i in MIN_PRUNE_AT..2 * MIN_PRUNE_AT - 1 {
+            assert_eq!(
+                limiter.admit_at(ip(i), start + secs(0.5)),
+                Admission::Accept
+            );
+        }
+        assert_eq!(buckets(), 2 * MIN_PRUNE_AT);
+
+        // by then the first addresses have refilled and are forgotten
+        assert_eq!(limiter.admit_at(IP, start + secs(0.75)), Admission::Accept);
+        assert_eq!(buckets(), MIN_PRUNE_AT);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::certificates::{load_certified_key, validity, CertSource, KeySource};
+use crate::ocsp::{self, OcspSource};
+use crate::sni_cert_resolver::dns_name;
+use crate::CertExpiry;
+
+use async_std::{io, task};
+
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, ClientHello, ResolvesServerCert};
+
+use std::fmt::{self, Debug, Formatter};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How often to check whether a fetched OCSP response needs to be
+/// replaced, either because it is due for a refresh or because the
+/// cert has been reloaded.
+const OCSP_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+type ModifiedTimes = (SystemTime, SystemTime, Option<SystemTime>);
+
+/// A [`ResolvesServerCert`] that serves a single cert and key, and
+/// that can be told to reload them when either file changes, if they
+/// were loaded from disk. Reloading only affects handshakes that begin
+/// after the swap, so connections already established are left alone.
+/// An OCSP response can be stapled to the cert, either reloaded from
+/// a file along with it or fetched in the background.
+pub(crate) struct ReloadingCertResolver {
+    cert: CertSource,
+    key: KeySource,
+    ocsp: Option<OcspSource>,
+    expiry: Option<(CertExpiry, String)>,
+    hostname: Option<String>,
+    certified_key: RwLock<CertifiedKey>,
+    modified: Mutex<Option<ModifiedTimes>>,
+}
+
+impl Debug for ReloadingCertResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadingCertResolver")
+            .field("cert", &self.cert)
+            .field("key", &self.key)
+            .field("ocsp", &self.ocsp)
+            .finish()
+    }
+}
+
+impl ReloadingCertResolver {
+    pub(crate) fn new(cert: CertSource, key: KeySource) -> io::Result<Self> {
+        let certified_key = load_certified_key(&cert, &key)?;
+        let modified = modified_times(&cert, &key, None).ok();
+
+        Ok(Self {
+            cert,
+            key,
+            ocsp: None,
+            expiry: None,
+            hostname: None,
+            certified_key: RwLock::new(certified_key),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// Staples an OCSP response to the cert. A response file is loaded
+    /// immediately, but fetching only begins with
+    /// [`ReloadingCertResolver::fetch_ocsp`].
+    pub(crate) fn with_ocsp(mut self, ocsp: OcspSource) -> io::Result<Self> {
+        if let OcspSource::Path(path) = &ocsp {
+            let certified_key = self.certified_key.get_mut().unwrap();
+            certified_key.ocsp = Some(ocsp::load(path, &certified_key.cert)?);
+        }
+
+        *self.modified.get_mut().unwrap() = modified_times(&self.cert, &self.key, Some(&ocsp)).ok();
+        self.ocsp = Some(ocsp);
+        Ok(self)
+    }
+
+    /// Records the expiry of the cert, and of each cert it is
+    /// reloaded with, under `name`.
+    pub(crate) fn with_expiry(mut self, expiry: CertExpiry, name: impl Into<String>) -> Self {
+        let name = name.into();
+        if let Some((_, not_after)) = validity(&self.certified_key.get_mut().unwrap().cert[0]) {
+            expiry.update(&name, not_after);
+        }
+        self.expiry = Some((expiry, name));
+        self
+    }
+
+    /// Checks that the cert, and each cert it is reloaded with, is
+    /// valid for `hostname`.
+    pub(crate) fn with_hostname(mut self, hostname: &str) -> io::Result<Self> {
+        check_hostname(self.certified_key.get_mut().unwrap(), hostname)?;
+        self.hostname = Some(hostname.to_string());
+        Ok(self)
+    }
+
+    pub(crate) fn certified_key(&self) -> CertifiedKey {
+        self.certified_key.read().unwrap().clone()
+    }
+
+    /// Spawns a task that checks the cert and key files for changes
+    /// every `interval`. The task holds only a weak reference, and
+    /// exits once the resolver is dropped. Unless both the cert and key
+    /// were loaded from files, this only logs that they will not be
+    /// reloaded.
+    pub(crate) fn watch(self: &Arc<Self>, interval: Duration) {
+        if self.cert.path().is_none() || self.key.path().is_none() {
+            tide::log::warn!("cert_reload_interval ignored for a cert and key held in memory", {
+                cert: self.expiry.as_ref().map_or("cert", |(_, name)| name.as_str()),
+            });
+            return;
+        }
+
+        let resolver = Arc::downgrade(self);
+        task::spawn(async move {
+            loop {
+                task::sleep(interval).await;
+                match resolver.upgrade() {
+                    Some(resolver) => resolver.reload_if_modified(),
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Spawns a task that fetches an OCSP response for the cert, and
+    /// fetches a new one whenever the current response is halfway to
+    /// expiring or the cert is reloaded. If a fetch fails, the
+    /// previous response is stapled until it expires. Like
+    /// [`ReloadingCertResolver::watch`], the task exits once the
+    /// resolver is dropped. This does nothing unless the resolver was
+    /// configured to fetch OCSP responses.
+    pub(crate) fn fetch_ocsp(self: &Arc<Self>) {
+        let responder = match &self.ocsp {
+            Some(OcspSource::Fetch(responder)) => responder.clone(),
+            _ => return,
+        };
+
+        let resolver = Arc::downgrade(self);
+        task::spawn(async move {
+            let mut refresh_at = None;
+            let mut expires_at = None;
+            loop {
+                let resolver = match resolver.upgrade() {
+                    Some(resolver) => resolver,
+                    None => break,
+                };
+
+                let certified_key = resolver.certified_key();
+                let now = SystemTime::now();
+                if certified_key.ocsp.is_none() || refresh_at.is_some_and(|at| at <= now) {
+                    match ocsp::fetch(&certified_key.cert, responder.as_deref()).await {
+                        Ok(response) => {
+                            refresh_at = Some(response.refresh_at());
+                            expires_at = response.next_update;
+                            resolver.staple(&certified_key.cert[0], Some(response.der));
+                        }
+
+                        Err(error) => {
+                            tide::log::warn!("unable to fetch ocsp response", {
+                                cert: display(resolver.cert.path()),
+                                error: error.to_string(),
+                            });
+
+                            if expires_at.is_some_and(|at| at <= now) {
+                                resolver.staple(&certified_key.cert[0], None);
+                            }
+                        }
+                    }
+                }
+
+                drop(resolver);
+                task::sleep(OCSP_CHECK_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Replaces the OCSP response stapled to `cert`, unless the cert
+    /// has been reloaded in the meantime.
+    fn staple(&self, cert: &Certificate, ocsp: Option<Vec<u8>>) {
+        let mut certified_key = self.certified_key.write().unwrap();
+        if certified_key.cert.first() == Some(cert) {
+            certified_key.ocsp = ocsp;
+        }
+    }
+
+    fn load(&self) -> io::Result<CertifiedKey> {
+        let mut certified_key = load_certified_key(&self.cert, &self.key)?;
+        if let Some(hostname) = &self.hostname {
+            check_hostname(&certified_key, hostname)?;
+        }
+
+        match &self.ocsp {
+            Some(OcspSource::Path(path)) => {
+                certified_key.ocsp = Some(ocsp::load(path, &certified_key.cert)?);
+            }
+
+            // Keep stapling a fetched response if the cert is unchanged.
+            Some(OcspSource::Fetch(_)) => {
+                let previous = self.certified_key();
+                if previous.cert.first() == certified_key.cert.first() {
+                    certified_key.ocsp = previous.ocsp;
+                }
+            }
+
+            None => {}
+        }
+        Ok(certified_key)
+    }
+
+    fn reload_if_modified(&self) {
+        let modified = match modified_times(&self.cert, &self.key, self.ocsp.as_ref()) {
+            Ok(modified) => modified,
+            Err(error) => {
+                tide::log::warn!("unable to check tls cert and key for changes", {
+                    cert: display(self.cert.path()),
+                    key: display(self.key.path()),
+                    error: error.to_string(),
+                });
+                return;
+            }
+        };
+
+        let mut last_modified = self.modified.lock().unwrap();
+        if *last_modified == Some(modified) {
+            return;
+        }
+        *last_modified = Some(modified);
+
+        match self.load() {
+            Ok(certified_key) => {
+                if let (Some((expiry, name)), Some((_, not_after))) =
+                    (&self.expiry, validity(&certified_key.cert[0]))
+                {
+                    expiry.update(name, not_after);
+                }
+                *self.certified_key.write().unwrap() = certified_key;
+                tide::log::info!("reloaded tls cert and key", {
+                    cert: display(self.cert.path()),
+                    key: display(self.key.path()),
+                });
+            }
+
+            Err(error) => {
+                tide::log::error!("invalid tls cert or key, continuing with previous", {
+                    cert: display(self.cert.path()),
+                    key: display(self.key.path()),
+                    error: error.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Reloads the cert and key regardless of their modified times,
+    /// since rewrites in tests may land within the filesystem's
+    /// timestamp granularity.
+    #[cfg(test)]
+    pub(crate) fn reload(&self) {
+        *self.modified.lock().unwrap() = None;
+        self.reload_if_modified();
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<CertifiedKey> {
+        Some(self.certified_key())
+    }
+}
+
+fn check_hostname(certified_key: &CertifiedKey, hostname: &str) -> io::Result<()> {
+    certified_key
+        .cross_check_end_entity_cert(Some(dns_name(hostname)?))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+fn modified_times(
+    cert: &CertSource,
+    key: &KeySource,
+    ocsp: Option<&OcspSource>,
+) -> io::Result<ModifiedTimes> {
+    let ocsp = match ocsp {
+        Some(OcspSource::Path(path)) => Some(modified_time(Some(path))?),
+        _ => None,
+    };
+    Ok((
+        modified_time(cert.path())?,
+        modified_time(key.path())?,
+        ocsp,
+    ))
+}
+
+fn modified_time(path: Option<&Path>) -> io::Result<SystemTime> {
+    match path {
+        Some(path) => std::fs::metadata(path)?.modified(),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "not a file")),
+    }
+}
+
+fn display(path: Option<&Path>) -> String {
+    path.map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EC_CERT: &[u8] = include_bytes!("../tests/fixtures/ec.pem");
+    const EC_KEY: &[u8] = include_bytes!("../tests/fixtures/ec-pkcs8.key");
+    const RSA_CERT: &[u8] = include_bytes!("../tests/fixtures/rsa.pem");
+    const RSA_KEY: &[u8] = include_bytes!("../tests/fixtures/rsa-pkcs8.key");
+
+    fn served_cert(resolver: &ReloadingCertResolver) -> Certificate {
+        resolver.certified_key().cert.remove(0)
+    }
+
+    fn parsed_cert(cert: &[u8], key: &[u8]) -> Certificate {
+        load_certified_key(
+            &CertSource::Pem(cert.to_vec()),
+            &KeySource::Pem(key.to_vec()),
+        )
+        .unwrap()
+        .cert
+        .remove(0)
+    }
+
+    #[test]
+    fn reloads_changed_files_and_keeps_serving_on_error() {
+        let dir = std::env::temp_dir().join(format!("tide-rustls-reload-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, EC_CERT).unwrap();
+        std::fs::write(&key_path, EC_KEY).unwrap();
+
+        let resolver = ReloadingCertResolver::new(
+            CertSource::Path(cert_path.clone()),
+            KeySource::Path(key_path.clone()),
+        )
+        .unwrap();
+        assert_eq!(served_cert(&resolver), parsed_cert(EC_CERT, EC_KEY));
+
+        std::fs::write(&cert_path, RSA_CERT).unwrap();
+        std::fs::write(&key_path, RSA_KEY).unwrap();
+        resolver.reload();
+        assert_eq!(served_cert(&resolver), parsed_cert(RSA_CERT, RSA_KEY));
+
+        std::fs::write(&cert_path, "not a cert").unwrap();
+        resolver.reload();
+        assert_eq!(served_cert(&resolver), parsed_cert(RSA_CERT, RSA_KEY));
+
+        // a key that does not match the cert is rejected the same way
+        std::fs::write(&cert_path, RSA_CERT).unwrap();
+        std::fs::write(&key_path, EC_KEY).unwrap();
+        resolver.reload();
+        assert_eq!(served_cert(&resolver), parsed_cert(RSA_CERT, RSA_KEY));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
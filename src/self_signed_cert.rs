@@ -0,0 +1,162 @@
+use crate::certificates::{write_key, CertSource, KeySource};
+
+use async_std::io;
+
+use rcgen::{Certificate, CertificateParams, DnType, SanType};
+
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// A self-signed certificate for local development, generated when
+/// the listener is bound, for use with
+/// [`TlsListenerBuilder::self_signed`](crate::TlsListenerBuilder::self_signed).
+///
+/// Browsers and other clients will not trust this certificate unless
+/// it is added to their trust store, which is only practical if it is
+/// [persisted](SelfSignedCert::persist) so that it stays the same
+/// across restarts. This should never be used in production.
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::{SelfSignedCert, TlsListener};
+/// let listener = TlsListener::<()>::build()
+///     .addrs("localhost:4433")
+///     .self_signed(SelfSignedCert::localhost().persist("./tls"))
+///     .finish();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SelfSignedCert {
+    names: Vec<String>,
+    persist: Option<PathBuf>,
+}
+
+impl Default for SelfSignedCert {
+    fn default() -> Self {
+        Self::localhost()
+    }
+}
+
+impl SelfSignedCert {
+    /// A certificate for `localhost`, `127.0.0.1` and `::1`.
+    pub fn localhost() -> Self {
+        Self::new(vec!["localhost", "127.0.0.1", "::1"])
+    }
+
+    /// A certificate for these hostnames and ip addresses.
+    pub fn new(names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            names: names.into_iter().map(Into::into).collect(),
+            persist: None,
+        }
+    }
+
+    /// Save the generated certificate and key in this directory,
+    /// which is created if it does not exist, and reuse them the next
+    /// time the listener is bound with the same names. The
+    /// certificate file can then be added to a browser or operating
+    /// system trust store once. On unix, the key file is readable
+    /// only by its owner.
+    pub fn persist(mut self, dir: impl AsRef<Path>) -> Self {
+        self.persist = Some(dir.as_ref().into());
+        self
+    }
+
+    pub(crate) fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Generates the certificate and key, or loads them from the
+    /// persist directory if they were generated there before.
+    pub(crate) fn load(&self) -> io::Result<(CertSource, KeySource)> {
+        let dir = match &self.persist {
+            Some(dir) => dir,
+            None => {
+                let (cert, key) = self.generate()?;
+                return Ok((CertSource::Pem(cert.into()), KeySource::Pem(key.into())));
+            }
+        };
+
+        let (cert_path, key_path) = self.paths(dir);
+        if !cert_path.exists() || !key_path.exists() {
+            let (cert, key) = self.generate()?;
+            std::fs::create_dir_all(dir)?;
+            write_key(&key_path, key.as_bytes())?;
+            std::fs::write(&cert_path, cert)?;
+            tide::log::info!("generated self-signed certificate", {
+                cert: cert_path.display().to_string(),
+            });
+        }
+
+        Ok((CertSource::Path(cert_path), KeySource::Path(key_path)))
+    }
+
+    fn generate(&self) -> io::Result<(String, String)> {
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "tide-rustls development certificate");
+        params.subject_alt_names = self
+            .names
+            .iter()
+            .map(|name| match name.parse::<IpAddr>() {
+                Ok(ip) => SanType::IpAddress(ip),
+                Err(_) => SanType::DnsName(name.clone()),
+            })
+            .collect();
+
+        let cert = Certificate::from_params(params).map_err(io::Error::other)?;
+        let pem = cert.serialize_pem().map_err(io::Error::other)?;
+        Ok((pem, cert.serialize_private_key_pem()))
+    }
+
+    /// Files are named after the names they cover, in the style of
+    /// mkcert, so that changing the names generates a new certificate.
+    fn paths(&self, dir: &Path) -> (PathBuf, PathBuf) {
+        let stem: String = self
+            .names
+            .join("+")
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '+' => c,
+                _ => '_',
+            })
+            .collect();
+
+        (
+            dir.join(format!("{}.cert.pem", stem)),
+            dir.join(format!("{}.key.pem", stem)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persisted_key_is_private_and_reused() {
+        let dir =
+            std::env::temp_dir().join(format!("tide-rustls-self-signed-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let self_signed = SelfSignedCert::localhost().persist(&dir);
+
+        let (_, key) = self_signed.load().unwrap();
+        let key_path = key.path().unwrap().to_path_buf();
+        let generated = std::fs::read(&key_path).unwrap();
+
+        let (cert, key) = self_signed.load().unwrap();
+        assert_eq!(key.path(), Some(&*key_path));
+        assert_eq!(std::fs::read(&key_path).unwrap(), generated);
+        assert!(crate::certificates::load_certified_key(&cert, &key).is_ok());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
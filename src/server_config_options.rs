@@ -0,0 +1,173 @@
+use crate::certificates::load_certs;
+use crate::key_log::{KeyLogDestination, KeyLogWriter};
+use crate::session_resumption::ResumptionOptions;
+use crate::tls_policy::ProtocolOptions;
+
+use async_std::io;
+
+use rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+    RootCertStore, ServerConfig,
+};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Options that apply to the [`ServerConfig`] that tide-rustls
+/// generates when it is given cert and key files, as opposed to a
+/// prebuilt [`ServerConfig`] or a custom acceptor.
+#[derive(Debug, Default)]
+pub(crate) struct ServerConfigOptions {
+    pub(crate) client_auth: Option<ClientAuth>,
+    conflicting_client_auth: bool,
+    pub(crate) key_log: Option<KeyLogDestination>,
+    pub(crate) protocols: ProtocolOptions,
+    pub(crate) resumption: ResumptionOptions,
+}
+
+#[derive(Debug)]
+pub(crate) enum ClientAuth {
+    Required(PathBuf),
+    Optional(PathBuf),
+}
+
+impl ServerConfigOptions {
+    /// Sets the client auth mode, remembering whether both modes were
+    /// requested so that the builder can reject the combination.
+    pub(crate) fn set_client_auth(&mut self, client_auth: ClientAuth) {
+        if let Some(previous) = &self.client_auth {
+            self.conflicting_client_auth |=
+                std::mem::discriminant(previous) != std::mem::discriminant(&client_auth);
+        }
+        self.client_auth = Some(client_auth);
+    }
+
+    /// Explains why these options conflict, if they do.
+    pub(crate) fn check(&self) -> Result<(), &'static str> {
+        if self.conflicting_client_auth {
+            return Err("client_auth_required and client_auth_optional are mutually exclusive");
+        }
+        self.resumption.check()
+    }
+
+    /// The name of an option that has been set, if any, for errors
+    /// when these options cannot be applied.
+    pub(crate) fn configured(&self) -> Option<&'static str> {
+        if self.client_auth.is_some() {
+            Some("client auth")
+        } else if self.key_log.is_some() {
+            Some("key logging")
+        } else if !self.protocols.is_default() {
+            Some("restricting tls versions or cipher suites")
+        } else if !self.resumption.is_default() {
+            Some("configuring session resumption")
+        } else {
+            None
+        }
+    }
+
+    /// Builds a [`ServerConfig`] with these options applied, but no
+    /// certificates.
+    pub(crate) fn server_config(&self) -> io::Result<ServerConfig> {
+        let client_cert_verifier = match &self.client_auth {
+            None => NoClientAuth::new(),
+            Some(ClientAuth::Required(ca)) => AllowAnyAuthenticatedClient::new(load_roots(ca)?),
+            Some(ClientAuth::Optional(ca)) => {
+                AllowAnyAnonymousOrAuthenticatedClient::new(load_roots(ca)?)
+            }
+        };
+
+        let mut config = ServerConfig::new(client_cert_verifier);
+        self.protocols.apply(&mut config)?;
+        self.resumption.apply(&mut config);
+        if let Some(key_log) = &self.key_log {
+            if let Some(key_log) = KeyLogWriter::open(key_log)? {
+                config.key_log = Arc::new(key_log);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn load_roots(path: &Path) -> io::Result<RootCertStore> {
+    let certs = load_certs(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("unable to read client ca file {}: {}", path.display(), err),
+        )
+    })?;
+
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no certificates found in client ca file {}", path.display()),
+        ));
+    }
+
+    let mut roots = RootCertStore::empty();
+    for cert in &certs {
+        roots.add(cert).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid certificate in client ca file {}: {}",
+                    path.display(),
+                    err
+                ),
+            )
+        })?;
+    }
+
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_a_ca_file() {
+        let roots = load_roots(Path::new("tests/fixtures/ca.pem")).unwrap();
+        assert_eq!(roots.len(), 1);
+    }
+
+    #[test]
+    fn missing_ca_file() {
+        let path = Path::new("tests/fixtures/missing-ca.pem");
+        let err = load_roots(path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(
+            err.to_string()
+                .starts_with("unable to read client ca file tests/fixtures/missing-ca.pem: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn empty_ca_file() {
+        let path = std::env::temp_dir().join(format!("tide-rustls-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+
+        let err = load_roots(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            format!("no certificates found in client ca file {}", path.display())
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn client_auth_needs_a_usable_ca_file() {
+        let mut options = ServerConfigOptions::default();
+        options.set_client_auth(ClientAuth::Required("tests/fixtures/missing-ca.pem".into()));
+        assert!(options.server_config().is_err());
+
+        let mut options = ServerConfigOptions::default();
+        options.set_client_auth(ClientAuth::Optional("tests/fixtures/ca.pem".into()));
+        assert!(options.server_config().is_ok());
+    }
+}
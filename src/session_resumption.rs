@@ -0,0 +1,416 @@
+use rustls::{NoServerSessionStorage, ProducesTickets, ServerConfig, ServerSessionMemoryCache};
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// A source of keys for stateless TLS session tickets, which lets
+/// clients resume sessions without the server keeping any state for
+/// them. This is provided with
+/// [`TlsListenerBuilder::session_tickets`](crate::TlsListenerBuilder::session_tickets).
+///
+/// A fresh random key is used to encrypt new tickets each rotation
+/// period, and the previous key is kept for one more period so that
+/// recently issued tickets can still be decrypted. Clones share the
+/// same keys, so one `TicketKeys` can be given to several listeners in
+/// the same process to let clients resume sessions on any of them.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use tide_rustls::{TicketKeys, TlsListener};
+/// let ticket_keys = TicketKeys::new(Duration::from_secs(60 * 60));
+///
+/// let public = TlsListener::<()>::build()
+///     .addrs("0.0.0.0:443")
+///     .cert("./tls/public.cert")
+///     .key("./tls/public.key")
+///     .session_tickets(ticket_keys.clone());
+///
+/// let internal = TlsListener::<()>::build()
+///     .addrs("0.0.0.0:8443")
+///     .cert("./tls/public.cert")
+///     .key("./tls/public.key")
+///     .session_tickets(ticket_keys);
+/// ```
+#[derive(Clone)]
+pub struct TicketKeys {
+    rotation: Duration,
+    keys: Arc<Mutex<Keys>>,
+}
+
+struct Keys {
+    current: LessSafeKey,
+    previous: Option<LessSafeKey>,
+    rotated_at: Instant,
+}
+
+impl TicketKeys {
+    /// Creates a source of ticket keys that switches to a new key after
+    /// each `rotation`, which is rounded up to one second. Tickets are
+    /// accepted for between one and two rotation periods after they
+    /// are issued, so this bounds how long a leaked key could be used
+    /// to decrypt recorded sessions.
+    pub fn new(rotation: Duration) -> Self {
+        Self {
+            rotation: rotation.max(Duration::from_secs(1)),
+            keys: Arc::new(Mutex::new(Keys {
+                current: random_key(),
+                previous: None,
+                rotated_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// How often a new key is used for new tickets.
+    pub fn rotation(&self) -> Duration {
+        self.rotation
+    }
+
+    /// The current keys, after switching to a new key if the current
+    /// one has been used for a full rotation period.
+    fn keys(&self) -> MutexGuard<'_, Keys> {
+        self.keys_at(Instant::now())
+    }
+
+    fn keys_at(&self, now: Instant) -> MutexGuard<'_, Keys> {
+        let mut keys = self.keys.lock().unwrap();
+        let elapsed = now.saturating_duration_since(keys.rotated_at);
+        if elapsed >= self.rotation {
+            let current = std::mem::replace(&mut keys.current, random_key());
+            // if no ticket was issued during the last period, the
+            // current key is already too old to keep
+            keys.previous = if elapsed < self.rotation * 2 {
+                Some(current)
+            } else {
+                None
+            };
+            keys.rotated_at = now;
+        }
+        keys
+    }
+}
+
+impl Default for TicketKeys {
+    /// Ticket keys that rotate every six hours, as rustls'
+    /// [`Ticketer`](rustls::Ticketer) does.
+    fn default() -> Self {
+        Self::new(Duration::from_secs(6 * 60 * 60))
+    }
+}
+
+impl Debug for TicketKeys {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketKeys")
+            .field("rotation", &self.rotation)
+            .finish()
+    }
+}
+
+impl ProducesTickets for TicketKeys {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn get_lifetime(&self) -> u32 {
+        self.rotation.as_secs().min(u64::from(u32::MAX)) as u32
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        seal(&self.keys().current, plain)
+    }
+
+    fn decrypt(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        open(&self.keys(), ticket)
+    }
+}
+
+fn seal(key: &LessSafeKey, plain: &[u8]) -> Option<Vec<u8>> {
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).ok()?;
+
+    let mut ticket = nonce.to_vec();
+    ticket.extend_from_slice(plain);
+    let tag = key
+        .seal_in_place_separate_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut ticket[NONCE_LEN..],
+        )
+        .ok()?;
+    ticket.extend_from_slice(tag.as_ref());
+    Some(ticket)
+}
+
+/// Decrypts a ticket with the current key, or failing that the
+/// previous one.
+fn open(keys: &Keys, ticket: &[u8]) -> Option<Vec<u8>> {
+    if ticket.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, sealed) = ticket.split_at(NONCE_LEN);
+
+    std::iter::once(&keys.current)
+        .chain(&keys.previous)
+        .find_map(|key| {
+            let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+            let mut plain = sealed.to_vec();
+            let len = key
+                .open_in_place(nonce, Aad::empty(), &mut plain)
+                .ok()?
+                .len();
+            plain.truncate(len);
+            Some(plain)
+        })
+}
+
+fn random_key() -> LessSafeKey {
+    let mut key = [0; 32];
+    SystemRandom::new()
+        .fill(&mut key)
+        .expect("unable to generate a session ticket key");
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap())
+}
+
+/// How clients may resume TLS sessions, as chosen with
+/// [`TlsListenerBuilder::session_cache_size`](crate::TlsListenerBuilder::session_cache_size),
+/// [`TlsListenerBuilder::session_tickets`](crate::TlsListenerBuilder::session_tickets),
+/// [`TlsListenerBuilder::session_ticketer`](crate::TlsListenerBuilder::session_ticketer)
+/// and
+/// [`TlsListenerBuilder::disable_session_resumption`](crate::TlsListenerBuilder::disable_session_resumption).
+#[derive(Default)]
+pub(crate) struct ResumptionOptions {
+    pub(crate) cache_size: Option<usize>,
+    pub(crate) ticketer: Option<Arc<dyn ProducesTickets>>,
+    pub(crate) disabled: bool,
+}
+
+impl Debug for ResumptionOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResumptionOptions")
+            .field("cache_size", &self.cache_size)
+            .field("tickets", &self.ticketer.is_some())
+            .field("disabled", &self.disabled)
+            .finish()
+    }
+}
+
+impl ResumptionOptions {
+    pub(crate) fn is_default(&self) -> bool {
+        self.cache_size.is_none() && self.ticketer.is_none() && !self.disabled
+    }
+
+    /// Explains why these options conflict, if they do.
+    pub(crate) fn check(&self) -> Result<(), &'static str> {
+        if self.disabled && (self.cache_size.is_some() || self.ticketer.is_some()) {
+            return Err(
+                "disable_session_resumption cannot be combined with a session cache size or tickets",
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn apply(&self, config: &mut ServerConfig) {
+        if self.disabled {
+            config.session_storage = Arc::new(NoServerSessionStorage {});
+            return;
+        }
+
+        match self.cache_size {
+            Some(0) => config.session_storage = Arc::new(NoServerSessionStorage {}),
+            Some(size) => config.session_storage = ServerSessionMemoryCache::new(size),
+            None => {}
+        }
+
+        if let Some(ticketer) = &self.ticketer {
+            config.ticketer = ticketer.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::NoClientAuth;
+
+    const ROTATION: Duration = Duration::from_secs(60);
+
+    fn encrypt_at(ticket_keys: &TicketKeys, plain: &[u8], now: Instant) -> Vec<u8> {
+        seal(&ticket_keys.keys_at(now).current, plain).unwrap()
+    }
+
+    fn decrypt_at(ticket_keys: &TicketKeys, ticket: &[u8], now: Instant) -> Option<Vec<u8>> {
+        open(&ticket_keys.keys_at(now), ticket)
+    }
+
+    #[test]
+    fn round_trip() {
+        let ticket_keys = TicketKeys::new(ROTATION);
+        let ticket = ticket_keys.encrypt(b"session state").unwrap();
+        assert_ne!(&ticket[NONCE_LEN..NONCE_LEN + 13], b"session state");
+        assert_eq!(ticket_keys.decrypt(&ticket).unwrap(), b"session state");
+        assert_ne!(ticket_keys.encrypt(b"session state").unwrap(), ticket);
+        assert_eq!(ticket_keys.get_lifetime(), 60);
+    }
+
+    #[test]
+    fn tickets_outlive_one_rotation_but_not_two() {
+        let ticket_keys = TicketKeys::new(ROTATION);
+        let start = Instant::now();
+        let ticket = encrypt_at(&ticket_keys, b"session state", start);
+
+        let rotated = start + ROTATION;
+        assert_eq!(
+            decrypt_at(&ticket_keys, &ticket, rotated).unwrap(),
+            b"session state"
+        );
+        let newer = encrypt_at(&ticket_keys, b"newer", rotated);
+
+        let rotated_twice = rotated + ROTATION;
+        assert_eq!(decrypt_at(&ticket_keys, &ticket, rotated_twice), None);
+        assert_eq!(
+            decrypt_at(&ticket_keys, &newer, rotated_twice).unwrap(),
+            b"newer"
+        );
+    }
+
+    #[test]
+    fn idle_keys_are_dropped_after_two_rotations() {
+        let ticket_keys = TicketKeys::new(ROTATION);
+        let start = Instant::now();
+        let ticket = encrypt_at(&ticket_keys, b"session state", start);
+        assert_eq!(
+            decrypt_at(&ticket_keys, &ticket, start + 2 * ROTATION),
+            None
+        );
+    }
+
+    #[test]
+    fn clones_share_keys() {
+        let ticket_keys = TicketKeys::new(ROTATION);
+        let clone = ticket_keys.clone();
+        let start = Instant::now();
+
+        let ticket = encrypt_at(&ticket_keys, b"session state", start);
+        assert_eq!(
+            decrypt_at(&clone, &ticket, start).unwrap(),
+            b"session state"
+        );
+
+        // a rotation through one clone is seen by the other
+        let rotated = encrypt_at(&clone, b"rotated", start + ROTATION);
+        assert_eq!(
+            decrypt_at(&ticket_keys, &rotated, start + ROTATION).unwrap(),
+            b"rotated"
+        );
+
+        let unrelated = TicketKeys::new(ROTATION);
+        assert_eq!(decrypt_at(&unrelated, &ticket, start), None);
+    }
+
+    #[test]
+    fn tampered_or_short_tickets() {
+        let ticket_keys = TicketKeys::new(ROTATION);
+        let ticket = ticket_keys.encrypt(b"session state").unwrap();
+
+        for i in [0, NONCE_LEN, ticket.len() - 1] {
+            let mut tampered = ticket.clone();
+            tampered[i] ^= 1;
+            assert_eq!(ticket_keys.decrypt(&tampered), None, "byte {}", i);
+        }
+
+        for len in [
+            0,
+            1,
+            NONCE_LEN - 1,
+            NONCE_LEN,
+            NONCE_LEN + 1,
+            ticket.len() - 1,
+        ] {
+            assert_eq!(ticket_keys.decrypt(&ticket[..len]), None, "{} bytes", len);
+        }
+    }
+
+    fn applied(options: &ResumptionOptions) -> ServerConfig {
+        options.check().unwrap();
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        options.apply(&mut config);
+        config
+    }
+
+    #[test]
+    fn default_options() {
+        let options = ResumptionOptions::default();
+        assert!(options.is_default());
+        let config = applied(&options);
+        assert!(config
+            .session_storage
+            .put(b"id".to_vec(), b"state".to_vec()));
+        assert!(!config.ticketer.enabled());
+    }
+
+    #[test]
+    fn zero_cache_size() {
+        let options = ResumptionOptions {
+            cache_size: Some(0),
+            ..ResumptionOptions::default()
+        };
+        let config = applied(&options);
+        assert!(!config
+            .session_storage
+            .put(b"id".to_vec(), b"state".to_vec()));
+        assert_eq!(config.session_storage.get(b"id"), None);
+    }
+
+    #[test]
+    fn cache_size_and_tickets() {
+        let options = ResumptionOptions {
+            cache_size: Some(10),
+            ticketer: Some(Arc::new(TicketKeys::new(ROTATION))),
+            ..ResumptionOptions::default()
+        };
+        let config = applied(&options);
+        assert!(config
+            .session_storage
+            .put(b"id".to_vec(), b"state".to_vec()));
+        assert_eq!(config.session_storage.get(b"id").unwrap(), b"state");
+        assert!(config.ticketer.enabled());
+    }
+
+    #[test]
+    fn disabled() {
+        let options = ResumptionOptions {
+            disabled: true,
+            ..ResumptionOptions::default()
+        };
+        let config = applied(&options);
+        assert!(!config
+            .session_storage
+            .put(b"id".to_vec(), b"state".to_vec()));
+        assert!(!config.ticketer.enabled());
+    }
+
+    #[test]
+    fn disabled_conflicts_with_cache_size_and_tickets() {
+        let options = ResumptionOptions {
+            disabled: true,
+            cache_size: Some(10),
+            ..ResumptionOptions::default()
+        };
+        assert!(options.check().is_err());
+
+        let options = ResumptionOptions {
+            disabled: true,
+            ticketer: Some(Arc::new(TicketKeys::new(ROTATION))),
+            ..ResumptionOptions::default()
+        };
+        assert_eq!(
+            options.check().unwrap_err(),
+            "disable_session_resumption cannot be combined with a session cache size or tickets"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use async_std::channel::{self, Receiver, Sender};
+
+/// A handle that stops a [`TlsListener`](crate::TlsListener) from
+/// accepting new connections and begins draining the connections it
+/// has already accepted.
+///
+/// Once [`ShutdownHandle::shutdown`] is called, the listener stops
+/// accepting tcp connections. Idle keep-alive connections are closed
+/// right away, and connections with a request in flight are closed
+/// once the response has been sent, each with a tls close_notify. Any
+/// connections still open after the
+/// [drain timeout](crate::TlsListenerBuilder::drain_timeout) are
+/// closed forcibly. The future returned by [`tide::Server::listen`]
+/// resolves once every connection has closed.
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
+/// # use std::time::Duration;
+/// # fn main() -> tide::Result<()> { async_std::task::block_on(async {
+/// let mut app = tide::new();
+/// app.at("/").get(|_| async { Ok("Hello tls") });
+///
+/// let listener = TlsListener::build()
+///     .addrs("localhost:4433")
+///     .cert("./tls/localhost-4433.cert")
+///     .key("./tls/localhost-4433.key")
+///     .drain_timeout(Duration::from_secs(30))
+///     .finish()?;
+///
+/// let shutdown = listener.shutdown_handle();
+/// async_std::task::spawn(async move {
+///     async_std::task::sleep(Duration::from_secs(60 * 60)).await;
+///     shutdown.shutdown();
+/// });
+///
+/// # if false {
+/// app.listen(listener).await?;
+/// # }
+/// # Ok(()) }) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shutdown: Signal,
+    force_close: Signal,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> Self {
+        Self {
+            shutdown: Signal::new(),
+            force_close: Signal::new(),
+        }
+    }
+
+    /// Begin shutting down the listener. Calling this more than once
+    /// has no additional effect.
+    pub fn shutdown(&self) {
+        self.shutdown.trigger();
+    }
+
+    /// Whether [`ShutdownHandle::shutdown`] has been called.
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.is_triggered()
+    }
+
+    /// Resolves once [`ShutdownHandle::shutdown`] has been called.
+    pub(crate) async fn wait(&self) {
+        self.shutdown.wait().await
+    }
+
+    /// Tells every remaining connection to close immediately.
+    pub(crate) fn force_close(&self) {
+        self.force_close.trigger();
+    }
+
+    /// Resolves once [`ShutdownHandle::force_close`] has been called.
+    pub(crate) async fn wait_for_force_close(&self) {
+        self.force_close.wait().await
+    }
+}
+
+/// A one-shot broadcast built on a channel that never carries a
+/// message: closing the channel wakes every pending receiver.
+#[derive(Debug, Clone)]
+struct Signal {
+    sender: Sender<()>,
+    receiver: Receiver<()>,
+}
+
+impl Signal {
+    fn new() -> Self {
+        let (sender, receiver) = channel::bounded(1);
+        Self { sender, receiver }
+    }
+
+    fn trigger(&self) {
+        self.sender.close();
+    }
+
+    fn is_triggered(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    async fn wait(&self) {
+        let _ = self.receiver.recv().await;
+    }
+}
@@ -0,0 +1,246 @@
+use crate::ReloadingCertResolver;
+
+use async_std::io;
+
+use rustls::sign::CertifiedKey;
+use rustls::{ClientHello, ResolvesServerCert};
+use webpki::DNSNameRef;
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+const WILDCARD_SAMPLE_LABEL: &str = "tide-rustls-wildcard";
+
+/// A [`ResolvesServerCert`] that picks a cert based on the SNI
+/// hostname sent by the client. Exact hostnames take precedence over
+/// wildcards of the form `*.example.com`, which match exactly one
+/// additional label. If nothing matches, the default cert is used if
+/// there is one, and otherwise the handshake is aborted.
+#[derive(Default)]
+pub(crate) struct SniCertResolver {
+    hostnames: HashMap<String, Arc<ReloadingCertResolver>>,
+    wildcards: HashMap<String, Arc<ReloadingCertResolver>>,
+    default: Option<Arc<ReloadingCertResolver>>,
+}
+
+impl Debug for SniCertResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SniCertResolver")
+            .field("hostnames", &self.hostnames.keys().collect::<Vec<_>>())
+            .field("wildcards", &self.wildcards.keys().collect::<Vec<_>>())
+            .field("default", &self.default.is_some())
+            .finish()
+    }
+}
+
+impl SniCertResolver {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a cert for `hostname`, which is either a dns name or
+    /// a wildcard such as `*.example.com`. Certs are checked to be
+    /// valid for the hostname, or for a name directly under a
+    /// wildcard's suffix, both now and whenever they are reloaded.
+    pub(crate) fn add(
+        &mut self,
+        hostname: &str,
+        resolver: ReloadingCertResolver,
+    ) -> io::Result<()> {
+        let hostname = hostname.to_ascii_lowercase();
+
+        let (map, name, resolver) = match hostname.strip_prefix("*.") {
+            Some(suffix) => {
+                dns_name(suffix)?;
+                // webpki can't parse `*.` names, so check the cert
+                // against a name that the wildcard would match
+                let sample = format!("{}.{}", WILDCARD_SAMPLE_LABEL, suffix);
+                (
+                    &mut self.wildcards,
+                    suffix.to_string(),
+                    resolver.with_hostname(&sample)?,
+                )
+            }
+
+            None => (
+                &mut self.hostnames,
+                hostname.clone(),
+                resolver.with_hostname(&hostname)?,
+            ),
+        };
+
+        if map.insert(name, Arc::new(resolver)).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("multiple certs provided for {}", hostname),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn set_default(&mut self, resolver: Arc<ReloadingCertResolver>) {
+        self.default = Some(resolver);
+    }
+
+    /// Calls [`ReloadingCertResolver::watch`] on every cert in this
+    /// resolver.
+    pub(crate) fn watch(&self, interval: std::time::Duration) {
+        self.hostnames
+            .values()
+            .chain(self.wildcards.values())
+            .chain(self.default.iter())
+            .for_each(|resolver| resolver.watch(interval));
+    }
+
+    fn lookup(&self, hostname: Option<&str>) -> Option<&Arc<ReloadingCertResolver>> {
+        hostname
+            .and_then(|hostname| {
+                let hostname = hostname.to_ascii_lowercase();
+                self.hostnames.get(&hostname).or_else(|| {
+                    let (_, parent) = hostname.split_once('.')?;
+                    self.wildcards.get(parent)
+                })
+            })
+            .or(self.default.as_ref())
+    }
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<CertifiedKey> {
+        self.lookup(client_hello.server_name().map(Into::into))
+            .map(|resolver| resolver.certified_key())
+    }
+}
+
+pub(crate) fn dns_name(hostname: &str) -> io::Result<DNSNameRef<'_>> {
+    DNSNameRef::try_from_ascii_str(hostname).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid sni hostname {}", hostname),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificates::{CertSource, KeySource};
+
+    const EC_CERT: &[u8] = include_bytes!("../tests/fixtures/ec.pem");
+    const EC_KEY: &[u8] = include_bytes!("../tests/fixtures/ec-pkcs8.key");
+    const RSA_CERT: &[u8] = include_bytes!("../tests/fixtures/rsa.pem");
+    const RSA_KEY: &[u8] = include_bytes!("../tests/fixtures/rsa-pkcs8.key");
+    const NO_SAN_CERT: &[u8] = include_bytes!("../tests/fixtures/rsa-4096.pem");
+    const NO_SAN_KEY: &[u8] = include_bytes!("../tests/fixtures/rsa-4096.key");
+    const WILDCARD_CERT: &[u8] = include_bytes!("../tests/fixtures/wildcard.pem");
+
+    fn cert(cert: &[u8], key: &[u8]) -> ReloadingCertResolver {
+        ReloadingCertResolver::new(CertSource::Pem(cert.to_vec()), KeySource::Pem(key.to_vec()))
+            .unwrap()
+    }
+
+    fn resolved(resolver: &SniCertResolver, hostname: Option<&str>) -> Option<rustls::Certificate> {
+        resolver
+            .lookup(hostname)
+            .map(|resolver| resolver.certified_key().cert.remove(0))
+    }
+
+    #[test]
+    fn exact_wildcard_and_default() {
+        let ec = cert(EC_CERT, EC_KEY).certified_key().cert.remove(0);
+        let rsa = cert(RSA_CERT, RSA_KEY).certified_key().cert.remove(0);
+        let wildcard = cert(WILDCARD_CERT, EC_KEY).certified_key().cert.remove(0);
+
+        let mut resolver = SniCertResolver::new();
+        resolver.add("LocalHost", cert(EC_CERT, EC_KEY)).unwrap();
+        resolver
+            .add("*.example.com", cert(WILDCARD_CERT, EC_KEY))
+            .unwrap();
+
+        assert_eq!(resolved(&resolver, Some("localhost")), Some(ec.clone()));
+        assert_eq!(resolved(&resolver, Some("LOCALHOST")), Some(ec.clone()));
+        assert_eq!(
+            resolved(&resolver, Some("www.example.com")),
+            Some(wildcard.clone())
+        );
+        assert_eq!(
+            resolved(&resolver, Some("API.Example.com")),
+            Some(wildcard.clone())
+        );
+        assert_eq!(resolved(&resolver, Some("example.com")), None);
+        assert_eq!(resolved(&resolver, Some("a.b.example.com")), None);
+        assert_eq!(resolved(&resolver, Some("example.org")), None);
+        assert_eq!(resolved(&resolver, None), None);
+
+        resolver.set_default(Arc::new(cert(RSA_CERT, RSA_KEY)));
+        assert_eq!(resolved(&resolver, Some("localhost")), Some(ec));
+        assert_eq!(resolved(&resolver, Some("www.example.com")), Some(wildcard));
+        assert_eq!(resolved(&resolver, Some("example.com")), Some(rsa.clone()));
+        assert_eq!(
+            resolved(&resolver, Some("a.b.example.com")),
+            Some(rsa.clone())
+        );
+        assert_eq!(resolved(&resolver, None), Some(rsa));
+    }
+
+    #[test]
+    fn rejects_duplicates_and_certs_for_other_hostnames() {
+        let mut resolver = SniCertResolver::new();
+        resolver.add("localhost", cert(EC_CERT, EC_KEY)).unwrap();
+        resolver
+            .add("*.example.com", cert(WILDCARD_CERT, EC_KEY))
+            .unwrap();
+
+        let err = resolver
+            .add("localhost", cert(RSA_CERT, RSA_KEY))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "multiple certs provided for localhost");
+        let err = resolver
+            .add("*.Example.com", cert(WILDCARD_CERT, EC_KEY))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "multiple certs provided for *.example.com");
+
+        let err = resolver
+            .add("example.com", cert(EC_CERT, EC_KEY))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = resolver
+            .add("*.example.org", cert(WILDCARD_CERT, EC_KEY))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = resolver
+            .add("*.example.net", cert(EC_CERT, EC_KEY))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(resolver.add("*.", cert(EC_CERT, EC_KEY)).is_err());
+    }
+
+    #[test]
+    fn reloaded_certs_are_checked_against_the_hostname() {
+        let dir = std::env::temp_dir().join(format!("tide-rustls-sni-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, EC_CERT).unwrap();
+        std::fs::write(&key_path, EC_KEY).unwrap();
+
+        let mut resolver = SniCertResolver::new();
+        let localhost = ReloadingCertResolver::new(
+            CertSource::Path(cert_path.clone()),
+            KeySource::Path(key_path.clone()),
+        )
+        .unwrap();
+        resolver.add("localhost", localhost).unwrap();
+        let served = resolved(&resolver, Some("localhost"));
+
+        std::fs::write(&cert_path, NO_SAN_CERT).unwrap();
+        std::fs::write(&key_path, NO_SAN_KEY).unwrap();
+        resolver.hostnames["localhost"].reload();
+        assert_eq!(resolved(&resolver, Some("localhost")), served);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -7,22 +7,27 @@ pub(crate) enum TcpConnection {
     Connected(TcpListener),
 }
 
-impl Display for TcpConnection {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+impl TcpConnection {
+    /// The port that this is, or will be, bound to.
+    pub(crate) fn port(&self) -> Option<u16> {
         match self {
-            Self::Addrs(addrs) => write!(
-                f,
-                "{}",
-                addrs
-                    .iter()
-                    .map(|a| format!("https://{}", a))
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            ),
+            Self::Addrs(addrs) => addrs.first().map(|a| a.port()),
+            Self::Connected(tcp) => tcp.local_addr().ok().map(|a| a.port()),
+        }
+    }
+
+    /// Formats the addresses as urls with the given scheme.
+    pub(crate) fn to_url_string(&self, scheme: &str) -> String {
+        match self {
+            Self::Addrs(addrs) => addrs
+                .iter()
+                .map(|a| format!("{}://{}", scheme, a))
+                .collect::<Vec<_>>()
+                .join(", "),
 
-            Self::Connected(tcp) => write!(
-                f,
-                "https://{}",
+            Self::Connected(tcp) => format!(
+                "{}://{}",
+                scheme,
                 tcp.local_addr()
                     .ok()
                     .map(|a| a.to_string())
@@ -32,3 +37,9 @@ impl Display for TcpConnection {
         }
     }
 }
+
+impl Display for TcpConnection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_url_string("https"))
+    }
+}
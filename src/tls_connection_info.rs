@@ -0,0 +1,63 @@
+use rustls::{CipherSuite, ProtocolVersion, ServerSession, Session};
+
+/// Parameters negotiated during the TLS handshake for a connection.
+///
+/// tide-rustls attaches this to every request as an extension, so it
+/// is available with `req.ext::<TlsConnectionInfo>()`.
+///
+/// # Example
+///
+/// ```rust
+/// # use tide_rustls::TlsConnectionInfo;
+/// async fn endpoint(req: tide::Request<()>) -> tide::Result<String> {
+///     let info = req.ext::<TlsConnectionInfo>().unwrap();
+///     Ok(format!(
+///         "{:?} {:?} {:?}",
+///         info.protocol_version(),
+///         info.cipher_suite(),
+///         info.sni_hostname()
+///     ))
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConnectionInfo {
+    protocol_version: Option<ProtocolVersion>,
+    cipher_suite: Option<CipherSuite>,
+    alpn_protocol: Option<Vec<u8>>,
+    sni_hostname: Option<String>,
+}
+
+impl TlsConnectionInfo {
+    pub(crate) fn new(session: &ServerSession) -> Self {
+        Self {
+            protocol_version: session.get_protocol_version(),
+            cipher_suite: session
+                .get_negotiated_ciphersuite()
+                .map(|suite| suite.suite),
+            alpn_protocol: session.get_alpn_protocol().map(Vec::from),
+            sni_hostname: session.get_sni_hostname().map(String::from),
+        }
+    }
+
+    /// The negotiated TLS protocol version, such as
+    /// [`ProtocolVersion::TLSv1_3`].
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.protocol_version
+    }
+
+    /// The negotiated cipher suite.
+    pub fn cipher_suite(&self) -> Option<CipherSuite> {
+        self.cipher_suite
+    }
+
+    /// The protocol selected with ALPN, if the client and server
+    /// agreed on one.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The hostname the client sent with SNI, if any.
+    pub fn sni_hostname(&self) -> Option<&str> {
+        self.sni_hostname.as_deref()
+    }
+}
@@ -1,51 +1,82 @@
+#[cfg(feature = "acme")]
+use crate::acme::{self, AcmeCertResolver, AcmeTlsAcceptor};
+use crate::cert_expiry;
+use crate::certificates::{CertSource, KeySource};
 use crate::custom_tls_acceptor::StandardTlsAcceptor;
+#[cfg(feature = "http2")]
+use crate::http2;
+use crate::listener_metrics::Rejection;
+use crate::rate_limit::Admission;
+use crate::tls_policy;
+use crate::{http_redirect, plaintext_http};
 use crate::{
-    CustomTlsAcceptor, TcpConnection, TlsListenerBuilder, TlsListenerConfig, TlsStreamWrapper,
+    ActiveConnections, ByteCounts, CertExpiry, ConnectionContext, ConnectionEventKind,
+    ConnectionEvents, ConnectionGuard, ConnectionLimitBehavior, CustomTlsAcceptor,
+    HandshakeFailure, ListenerMetrics, ListenerOptions, PlaintextHttp, ReloadingCertResolver,
+    ShutdownHandle, SniCertResolver, TcpConnection, TlsConnectionInfo, TlsListenerBuilder,
+    TlsListenerConfig, TlsStreamWrapper,
 };
 
+use tide::http::headers::CONNECTION;
 use tide::listener::ListenInfo;
 use tide::listener::{Listener, ToListener};
 use tide::Server;
 
+use async_lock::{Semaphore, SemaphoreGuardArc};
+use async_std::channel::{self, Receiver};
+use async_std::io::{Read, Write};
 use async_std::net::{TcpListener, TcpStream};
 use async_std::prelude::*;
-use async_std::{io, task};
+use async_std::{future, io, task};
 
+use async_h1::server::ConnectionStatus;
+use futures_lite::{AsyncWriteExt, FutureExt};
+
+use async_rustls::server::TlsStream;
 use async_rustls::TlsAcceptor;
-use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
-use rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig};
+use rustls::ServerConfig;
 
 use std::fmt::{self, Debug, Display, Formatter};
-use std::fs::File;
-use std::io::{BufReader, Seek, SeekFrom};
-use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::Duration;
 
 /// The primary type for this crate
 pub struct TlsListener<State> {
     connection: TcpConnection,
+    http_redirect: Option<TcpConnection>,
     config: TlsListenerConfig,
     server: Option<Server<State>>,
-    tcp_nodelay: Option<bool>,
-    tcp_ttl: Option<u32>,
+    options: Arc<ListenerOptions>,
+    shutdown: ShutdownHandle,
+    active_connections: ActiveConnections,
+    connection_limit: Option<Arc<Semaphore>>,
+    cert_expiry: CertExpiry,
+    metrics: ListenerMetrics,
 }
 
 impl<State> Debug for TlsListener<State> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("TlsListener")
-            .field(&"connection", &self.connection)
-            .field(&"config", &self.config)
+            .field("connection", &self.connection)
+            .field("http_redirect", &self.http_redirect)
+            .field("config", &self.config)
             .field(
-                &"server",
+                "server",
                 if self.server.is_some() {
                     &"Some(Server<State>)"
                 } else {
                     &"None"
                 },
             )
-            .field("tcp_ttl", &self.tcp_ttl)
-            .field("tcp_nodelay", &self.tcp_nodelay)
+            .field("options", &self.options)
+            .field("shutdown", &self.shutdown)
+            .field("active_connections", &self.active_connections)
+            .field("connection_limit", &self.connection_limit)
+            .field("cert_expiry", &self.cert_expiry)
+            .field("metrics", &self.metrics)
             .finish()
     }
 }
@@ -53,18 +84,29 @@ impl<State> Debug for TlsListener<State> {
 impl<State> TlsListener<State> {
     pub(crate) fn new(
         connection: TcpConnection,
+        http_redirect: Option<TcpConnection>,
         config: TlsListenerConfig,
-        tcp_nodelay: Option<bool>,
-        tcp_ttl: Option<u32>,
+        options: ListenerOptions,
     ) -> Self {
+        let connection_limit = options
+            .max_connections
+            .map(|max_connections| Arc::new(Semaphore::new(max_connections)));
+
+        let active_connections = ActiveConnections::new();
         Self {
             connection,
+            http_redirect,
             config,
             server: None,
-            tcp_nodelay,
-            tcp_ttl,
+            options: Arc::new(options),
+            shutdown: ShutdownHandle::new(),
+            metrics: ListenerMetrics::new(active_connections.clone()),
+            active_connections,
+            connection_limit,
+            cert_expiry: CertExpiry::new(),
         }
     }
+
     /// The primary entrypoint to create a TlsListener. See
     /// [TlsListenerBuilder](crate::TlsListenerBuilder) for more
     /// configuration options.
@@ -83,38 +125,173 @@ impl<State> TlsListener<State> {
         TlsListenerBuilder::new()
     }
 
+    /// Returns a [`ShutdownHandle`] that can be used to stop this
+    /// listener and drain its connections after it has been passed to
+    /// [`tide::Server::listen`].
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Returns an [`ActiveConnections`] handle that reports how many
+    /// connections this listener is serving, and that remains usable
+    /// after the listener has been passed to [`tide::Server::listen`].
+    pub fn active_connections(&self) -> ActiveConnections {
+        self.active_connections.clone()
+    }
+
+    /// Returns a [`CertExpiry`] handle that reports when the
+    /// certificates this listener serves expire.
+    pub fn cert_expiry(&self) -> CertExpiry {
+        self.cert_expiry.clone()
+    }
+
+    /// Returns a [`ListenerMetrics`] handle to the metrics this
+    /// listener keeps about its connections.
+    pub fn metrics(&self) -> ListenerMetrics {
+        self.metrics.clone()
+    }
+
     async fn configure(&mut self) -> io::Result<()> {
         self.config = match std::mem::take(&mut self.config) {
-            TlsListenerConfig::Paths { cert, key } => {
-                let certs = load_certs(&cert)?;
-                let mut keys = load_keys(&key)?;
-                let mut config = ServerConfig::new(NoClientAuth::new());
-                config
-                    .set_single_cert(certs, keys.remove(0))
-                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
-
-                TlsListenerConfig::Acceptor(Arc::new(StandardTlsAcceptor(TlsAcceptor::from(
-                    Arc::new(config),
-                ))))
+            TlsListenerConfig::CertAndKey {
+                cert,
+                key,
+                reload_interval,
+                ocsp,
+                options,
+            } => {
+                let name = cert_name(&cert);
+                let mut resolver = ReloadingCertResolver::new(cert, key)?
+                    .with_expiry(self.cert_expiry.clone(), name.as_str());
+                if let Some(ocsp) = ocsp {
+                    resolver = resolver.with_ocsp(ocsp)?;
+                }
+
+                let mut config = options.server_config()?;
+                tls_policy::check_key(&config, &**resolver.certified_key().key, &name)?;
+
+                let resolver = Arc::new(resolver);
+                if let Some(reload_interval) = reload_interval {
+                    resolver.watch(reload_interval);
+                }
+                resolver.fetch_ocsp();
+
+                config.cert_resolver = resolver;
+                self.standard_acceptor(config)
+            }
+
+            TlsListenerConfig::Sni {
+                certs,
+                default,
+                reload_interval,
+                options,
+            } => {
+                let mut config = options.server_config()?;
+                let mut resolver = SniCertResolver::new();
+                for (hostname, cert, key) in certs {
+                    let cert_resolver =
+                        ReloadingCertResolver::new(CertSource::Path(cert), KeySource::Path(key))?
+                            .with_expiry(self.cert_expiry.clone(), hostname.as_str());
+                    let certified_key = cert_resolver.certified_key();
+                    tls_policy::check_key(&config, &**certified_key.key, &hostname)?;
+                    resolver.add(&hostname, cert_resolver)?;
+                }
+
+                if let Some((cert, key)) = default {
+                    let name = cert_name(&cert);
+                    let default = ReloadingCertResolver::new(cert, key)?
+                        .with_expiry(self.cert_expiry.clone(), name.as_str());
+                    tls_policy::check_key(&config, &**default.certified_key().key, &name)?;
+                    resolver.set_default(Arc::new(default));
+                }
+
+                if let Some(reload_interval) = reload_interval {
+                    resolver.watch(reload_interval);
+                }
+
+                config.cert_resolver = Arc::new(resolver);
+                self.standard_acceptor(config)
+            }
+
+            #[cfg(feature = "acme")]
+            TlsListenerConfig::Acme { acme, options } => {
+                let mut config = options.server_config()?;
+                tls_policy::check_acme_key(&config)?;
+
+                let resolver =
+                    Arc::new(AcmeCertResolver::new(acme)?.with_expiry(self.cert_expiry.clone()));
+                resolver.provision();
+
+                config.cert_resolver = resolver;
+                config.alpn_protocols = self.alpn_protocols();
+                config.alpn_protocols.push(acme::ACME_TLS_ALPN.to_vec());
+                TlsListenerConfig::Acceptor(Arc::new(AcmeTlsAcceptor(TlsAcceptor::from(Arc::new(
+                    config,
+                )))))
             }
 
-            TlsListenerConfig::ServerConfig(config) => TlsListenerConfig::Acceptor(Arc::new(
-                StandardTlsAcceptor(TlsAcceptor::from(Arc::new(config))),
-            )),
+            #[cfg(feature = "self-signed")]
+            TlsListenerConfig::SelfSigned { cert, options } => {
+                let (cert_source, key_source) = cert.load()?;
+                let name = cert.names().join(", ");
+                let resolver = ReloadingCertResolver::new(cert_source, key_source)?
+                    .with_expiry(self.cert_expiry.clone(), name.as_str());
+                tide::log::warn!("serving a self-signed certificate, for development only", {
+                    names: name.as_str(),
+                });
+
+                let mut config = options.server_config()?;
+                tls_policy::check_key(&config, &**resolver.certified_key().key, &name)?;
+                config.cert_resolver = Arc::new(resolver);
+                self.standard_acceptor(config)
+            }
+
+            TlsListenerConfig::ServerConfig(config) => self.standard_acceptor(config),
 
             other @ TlsListenerConfig::Acceptor(_) => other,
 
             TlsListenerConfig::Unconfigured => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "could not configure tlslistener",
-                ));
+                return Err(io::Error::other("could not configure tlslistener"));
             }
         };
 
+        let cert_expiry = self.cert_expiry.clone();
+        let shutdown = self.shutdown.clone();
+        let thresholds = self
+            .options
+            .cert_expiry_warnings
+            .clone()
+            .unwrap_or_else(|| cert_expiry::DEFAULT_WARNINGS.to_vec());
+        task::spawn(async move {
+            cert_expiry.monitor(thresholds).or(shutdown.wait()).await;
+        });
+
         Ok(())
     }
 
+    /// Wraps a [`ServerConfig`] in an acceptor, first advertising the
+    /// protocols this listener serves unless the config already
+    /// specifies its own.
+    fn standard_acceptor(&self, mut config: ServerConfig) -> TlsListenerConfig {
+        if config.alpn_protocols.is_empty() {
+            config.alpn_protocols = self.alpn_protocols();
+        }
+
+        TlsListenerConfig::Acceptor(Arc::new(StandardTlsAcceptor(TlsAcceptor::from(Arc::new(
+            config,
+        )))))
+    }
+
+    /// The http versions to advertise with ALPN, if any need to be.
+    fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        #[cfg(feature = "http2")]
+        if self.options.http2 {
+            return vec![http2::H2.to_vec(), http2::HTTP_1_1.to_vec()];
+        }
+
+        Vec::new()
+    }
+
     fn acceptor(&self) -> Option<&Arc<dyn CustomTlsAcceptor>> {
         match self.config {
             TlsListenerConfig::Acceptor(ref a) => Some(a),
@@ -134,44 +311,373 @@ impl<State> TlsListener<State> {
             let tcp = TcpListener::bind(&addrs[..]).await?;
             self.connection = TcpConnection::Connected(tcp);
         }
+
+        if let Some(TcpConnection::Addrs(addrs)) = &self.http_redirect {
+            let tcp = TcpListener::bind(&addrs[..]).await?;
+            self.http_redirect = Some(TcpConnection::Connected(tcp));
+        }
+
         Ok(())
     }
+
+    /// Waits for every connection to close once the listener has
+    /// stopped accepting, forcing them closed if they outlast the
+    /// drain timeout.
+    async fn drain(&self, connections: Receiver<()>) {
+        tide::log::info!("tls listener shutting down", {
+            connections: connections.sender_count(),
+        });
+
+        if let Some(drain_timeout) = self.options.drain_timeout {
+            if future::timeout(drain_timeout, connections.recv())
+                .await
+                .is_ok()
+            {
+                return;
+            }
+
+            tide::log::warn!("drain timeout elapsed, closing remaining connections", {
+                connections: connections.sender_count(),
+            });
+            self.shutdown.force_close();
+        }
+
+        let _ = connections.recv().await;
+    }
+}
+
+/// Takes a place under `max_connections` for a connection whose rate
+/// limit delay is over, in the same way as the accept loop: waiting
+/// for one with [`ConnectionLimitBehavior::Backpressure`], or giving
+/// up right away with [`ConnectionLimitBehavior::Close`]. This returns
+/// `None` if the connection should be closed instead.
+async fn delayed_permit(
+    connection_limit: &Arc<Semaphore>,
+    options: &ListenerOptions,
+    shutdown: &ShutdownHandle,
+    metrics: &ListenerMetrics,
+) -> Option<SemaphoreGuardArc> {
+    match options.connection_limit_behavior {
+        ConnectionLimitBehavior::Backpressure => {
+            async { Some(connection_limit.acquire_arc().await) }
+                .or(async {
+                    shutdown.wait().await;
+                    None
+                })
+                .await
+        }
+
+        ConnectionLimitBehavior::Close => {
+            let permit = connection_limit.try_acquire_arc();
+            if permit.is_none() {
+                tide::log::warn!("max connections reached, closing connection", {
+                    max_connections: options.max_connections,
+                });
+                metrics.record_rejection(Rejection::ConnectionLimit);
+            }
+            permit
+        }
+    }
 }
 
 fn handle_tls<State: Clone + Send + Sync + 'static>(
     app: Server<State>,
     stream: TcpStream,
     acceptor: Arc<dyn CustomTlsAcceptor>,
+    options: Arc<ListenerOptions>,
+    shutdown: ShutdownHandle,
+    metrics: ListenerMetrics,
+    guard: ConnectionGuard,
 ) {
     task::spawn(async move {
-        let local_addr = stream.local_addr().ok();
-        let peer_addr = stream.peer_addr().ok();
+        let mut events = ConnectionEvents::new(
+            metrics,
+            options.connection_observer.clone(),
+            stream.peer_addr().ok(),
+        );
+        let byte_counts = ByteCounts::default();
+        events.emit(ConnectionEventKind::Accepted);
 
-        match acceptor.accept(stream).await {
-            Ok(None) => {}
+        serve_tls(
+            app,
+            stream,
+            acceptor,
+            &options,
+            &shutdown,
+            &mut events,
+            &byte_counts,
+        )
+        .or(shutdown.wait_for_force_close())
+        .await;
 
-            Ok(Some(tls_stream)) => {
-                let stream = TlsStreamWrapper::new(tls_stream);
-                let fut = async_h1::accept(stream, |mut req| async {
-                    if req.url_mut().set_scheme("https").is_err() {
-                        tide::log::error!("unable to set https scheme on url", { url: req.url().to_string() });
-                    }
+        events.emit(ConnectionEventKind::Closed {
+            duration: events.elapsed(),
+            bytes_read: byte_counts.read(),
+            bytes_written: byte_counts.written(),
+        });
+        drop(guard);
+    });
+}
+
+async fn serve_tls<State: Clone + Send + Sync + 'static>(
+    app: Server<State>,
+    stream: TcpStream,
+    acceptor: Arc<dyn CustomTlsAcceptor>,
+    options: &ListenerOptions,
+    shutdown: &ShutdownHandle,
+    events: &mut ConnectionEvents,
+    byte_counts: &ByteCounts,
+) {
+    let local_addr = stream.local_addr().ok();
+    let peer_addr = stream.peer_addr().ok();
+    // rate limits apply to the tcp peer even when a PROXY protocol
+    // header names another address
+    let record_failure = || {
+        if let Some(peer_addr) = peer_addr {
+            options.rate_limiter.record_failure(peer_addr.ip());
+        }
+    };
+    let display_peer_addr =
+        || peer_addr.map_or_else(|| "[unknown]".into(), |addr| addr.to_string());
+
+    let handshake = async {
+        let source_addr = match options.proxy_protocol {
+            Some(proxy_protocol) => match proxy_protocol.read_header(&stream).await {
+                Ok(source_addr) => source_addr,
+                Err(error) => {
+                    tide::log::warn!("proxy protocol error", {
+                        error: error.to_string(),
+                        peer_addr: display_peer_addr(),
+                    });
+                    return Err((HandshakeFailure::ProxyProtocol, error));
+                }
+            },
+            None => None,
+        };
+
+        if let Some(plaintext_http) = options.plaintext_http {
+            match plaintext_http::is_tls(&stream).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Ok((source_addr, Handshake::Plaintext(stream, plaintext_http)));
+                }
+                Err(error) => return Err((HandshakeFailure::Io, error)),
+            }
+        }
+
+        Ok((
+            source_addr,
+            Handshake::Tls(Box::new(acceptor.accept(stream).await)),
+        ))
+    };
+
+    let handshake = async {
+        match options.handshake_timeout {
+            Some(handshake_timeout) => match future::timeout(handshake_timeout, handshake).await {
+                Ok(handshake) => handshake,
+                Err(error) => {
+                    tide::log::warn!("tls handshake timed out", { peer_addr: display_peer_addr() });
+                    Err((
+                        HandshakeFailure::Timeout,
+                        io::Error::new(io::ErrorKind::TimedOut, error),
+                    ))
+                }
+            },
+
+            None => handshake.await,
+        }
+    };
 
-                    req.set_local_addr(local_addr);
-                    req.set_peer_addr(peer_addr);
-                    app.respond(req).await
+    // A connection that has not finished its handshake has no request
+    // in flight, so it is closed as soon as the listener shuts down
+    // rather than holding up the drain.
+    let handshake = async { Some(handshake.await) }.or(async {
+        shutdown.wait().await;
+        None
+    });
+    let handshake = match handshake.await {
+        Some(handshake) => handshake,
+        None => return,
+    };
+
+    let (source_addr, handshake) = match handshake {
+        Ok(handshake) => handshake,
+        Err((reason, error)) => {
+            record_failure();
+            events.emit(ConnectionEventKind::HandshakeFailed {
+                duration: events.elapsed(),
+                reason,
+                error: error.to_string(),
+            });
+            return;
+        }
+    };
+    let peer_addr = source_addr.or(peer_addr);
+    if let Some(source_addr) = source_addr {
+        events.set_peer_addr(source_addr);
+    }
+
+    let handshake = match handshake {
+        Handshake::Tls(handshake) => *handshake,
+
+        Handshake::Plaintext(stream, PlaintextHttp::Redirect) => {
+            let https_port = local_addr.map_or(443, |addr| addr.port());
+            http_redirect::serve(stream, https_port, shutdown).await;
+            return;
+        }
+
+        Handshake::Plaintext(stream, PlaintextHttp::Serve) => {
+            let context = ConnectionContext::plaintext(local_addr, peer_addr);
+            if let Err(error) = serve_http1(app, stream, context, shutdown).await {
+                tide::log::error!("async-h1 error", { error: error.to_string() });
+                events.emit(ConnectionEventKind::HttpError {
+                    error: error.to_string(),
                 });
+            }
+            return;
+        }
+    };
+
+    match handshake {
+        Ok(None) => {}
 
-                if let Err(error) = fut.await {
-                    tide::log::error!("async-h1 error", { error: error.to_string() });
+        Ok(Some(tls_stream)) => {
+            let session = tls_stream.get_ref().1;
+            events.emit(ConnectionEventKind::HandshakeCompleted {
+                duration: events.elapsed(),
+                info: TlsConnectionInfo::new(session),
+            });
+            let context = ConnectionContext::new(local_addr, peer_addr, session);
+            #[cfg(feature = "http2")]
+            let negotiated_h2 = options.http2 && http2::negotiated(session);
+
+            let mut stream = TlsStreamWrapper::new(tls_stream, byte_counts.clone());
+
+            #[cfg(feature = "http2")]
+            if negotiated_h2 {
+                if let Err(error) = http2::serve(app, stream.clone(), context, shutdown).await {
+                    tide::log::error!("h2 error", { error: error.to_string() });
+                    events.emit(ConnectionEventKind::HttpError {
+                        error: error.to_string(),
+                    });
                 }
+                let _ = stream.close().await;
+                return;
             }
 
-            Err(tls_error) => {
-                tide::log::error!("tls error", { error: tls_error.to_string() });
+            if let Err(error) = serve_http1(app, stream.clone(), context, shutdown).await {
+                tide::log::error!("async-h1 error", { error: error.to_string() });
+                events.emit(ConnectionEventKind::HttpError {
+                    error: error.to_string(),
+                });
             }
+            let _ = stream.close().await;
         }
+
+        Err(tls_error) => {
+            tide::log::error!("tls error", { error: tls_error.to_string() });
+            record_failure();
+            events.emit(ConnectionEventKind::HandshakeFailed {
+                duration: events.elapsed(),
+                reason: HandshakeFailure::from_error(&tls_error),
+                error: tls_error.to_string(),
+            });
+        }
+    }
+}
+
+/// What a connection turned out to be once any PROXY protocol header
+/// and the start of the TLS handshake have been read.
+enum Handshake {
+    Tls(Box<io::Result<Option<TlsStream<TcpStream>>>>),
+    Plaintext(TcpStream, PlaintextHttp),
+}
+
+async fn serve_http1<State, RW>(
+    app: Server<State>,
+    stream: RW,
+    context: ConnectionContext,
+    shutdown: &ShutdownHandle,
+) -> tide::http::Result<()>
+where
+    State: Clone + Send + Sync + 'static,
+    RW: Read + Write + Clone + Send + Sync + Unpin + 'static,
+{
+    let in_request = Arc::new(AtomicBool::new(false));
+    let stream = RequestStartStream {
+        stream,
+        in_request: in_request.clone(),
+    };
+    let mut server = async_h1::server::Server::new(stream, |mut req| async {
+        context.apply(&mut req);
+
+        let mut res: tide::http::Response = app.respond(req).await?;
+        if shutdown.is_shutdown() {
+            res.insert_header(CONNECTION, "close");
+        }
+        Ok(res)
     });
+
+    // Between requests, a shutdown closes the connection right away.
+    // Once the first byte of a request has arrived, the response is
+    // sent with `connection: close` instead.
+    loop {
+        let idle_shutdown = async {
+            shutdown.wait().await;
+            if in_request.load(Ordering::SeqCst) {
+                future::pending::<()>().await;
+            }
+            Ok(ConnectionStatus::Close)
+        };
+
+        match server.accept_one().or(idle_shutdown).await? {
+            ConnectionStatus::KeepAlive => in_request.store(false, Ordering::SeqCst),
+            ConnectionStatus::Close => return Ok(()),
+        }
+    }
+}
+
+/// Marks a connection as being in a request as soon as any of the
+/// request is read, so that a shutdown does not cut off a request
+/// whose head has only partly arrived.
+#[derive(Clone)]
+struct RequestStartStream<RW> {
+    stream: RW,
+    in_request: Arc<AtomicBool>,
+}
+
+impl<RW: Read + Unpin> Read for RequestStartStream<RW> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(bytes)) = poll {
+            if bytes > 0 {
+                self.in_request.store(true, Ordering::SeqCst);
+            }
+        }
+        poll
+    }
+}
+
+impl<RW: Write + Unpin> Write for RequestStartStream<RW> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
 }
 
 impl<State: Clone + Send + Sync + 'static> ToListener<State> for TlsListener<State> {
@@ -198,12 +704,69 @@ impl<State: Clone + Send + Sync + 'static> Listener<State> for TlsListener<State
     }
 
     async fn accept(&mut self) -> io::Result<()> {
+        futures_lite::future::try_zip(self.accept_tls(), self.accept_http_redirect())
+            .await
+            .map(|_| ())
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        let mut info = vec![ListenInfo::new(
+            self.connection.to_string(),
+            String::from("tcp"),
+            true,
+        )];
+
+        if let Some(http_redirect) = &self.http_redirect {
+            info.push(ListenInfo::new(
+                http_redirect.to_url_string("http"),
+                String::from("tcp"),
+                false,
+            ));
+        }
+
+        info
+    }
+}
+
+impl<State: Clone + Send + Sync + 'static> TlsListener<State> {
+    async fn accept_tls(&self) -> io::Result<()> {
         let listener = self.tcp().unwrap();
         let mut incoming = listener.incoming();
         let acceptor = self.acceptor().unwrap();
         let server = self.server.as_ref().unwrap();
+        let (drain, connections) = channel::bounded(1);
+
+        loop {
+            let mut permit = None;
+            if let (Some(connection_limit), ConnectionLimitBehavior::Backpressure) = (
+                &self.connection_limit,
+                self.options.connection_limit_behavior,
+            ) {
+                permit = async { Some(connection_limit.acquire_arc().await) }
+                    .or(async {
+                        self.shutdown.wait().await;
+                        None
+                    })
+                    .await;
+
+                if permit.is_none() {
+                    break;
+                }
+            }
+
+            let stream = incoming
+                .next()
+                .or(async {
+                    self.shutdown.wait().await;
+                    None
+                })
+                .await;
+
+            let stream = match stream {
+                Some(stream) => stream,
+                None => break,
+            };
 
-        while let Some(stream) = incoming.next().await {
             match stream {
                 Err(ref e) if is_transient_error(e) => continue,
 
@@ -215,30 +778,162 @@ impl<State: Clone + Send + Sync + 'static> Listener<State> for TlsListener<State
                 }
 
                 Ok(stream) => {
-                    if let Some(nodelay) = self.tcp_nodelay {
-                        stream.set_nodelay(nodelay)?;
+                    let admission = match stream.peer_addr() {
+                        Ok(peer_addr) => self.options.rate_limiter.admit(peer_addr.ip()),
+                        Err(_) => Admission::Accept,
+                    };
+                    if admission == Admission::Close {
+                        self.metrics.record_rejection(Rejection::RateLimit);
+                        continue;
+                    }
+
+                    if let (Admission::Accept, None, Some(connection_limit)) =
+                        (admission, &permit, &self.connection_limit)
+                    {
+                        permit = connection_limit.try_acquire_arc();
+                        if permit.is_none() {
+                            tide::log::warn!("max connections reached, closing connection", {
+                                max_connections: self.options.max_connections,
+                            });
+                            self.metrics.record_rejection(Rejection::ConnectionLimit);
+                            continue;
+                        }
                     }
 
-                    if let Some(ttl) = self.tcp_ttl {
-                        stream.set_ttl(ttl)?;
+                    if let Some(nodelay) = self.options.tcp_nodelay {
+                        if let Err(error) = stream.set_nodelay(nodelay) {
+                            tide::log::warn!("unable to set tcp nodelay, closing connection", {
+                                error: error.to_string(),
+                            });
+                            continue;
+                        }
                     }
 
-                    handle_tls(server.clone(), stream, acceptor.clone())
+                    if let Some(ttl) = self.options.tcp_ttl {
+                        if let Err(error) = stream.set_ttl(ttl) {
+                            tide::log::warn!("unable to set tcp ttl, closing connection", {
+                                error: error.to_string(),
+                            });
+                            continue;
+                        }
+                    }
+
+                    let handle = {
+                        let server = server.clone();
+                        let acceptor = acceptor.clone();
+                        let options = self.options.clone();
+                        let shutdown = self.shutdown.clone();
+                        let metrics = self.metrics.clone();
+                        let active_connections = self.active_connections.clone();
+                        let drain = drain.clone();
+                        move |permit| {
+                            let guard = active_connections.track(drain, permit);
+                            handle_tls(server, stream, acceptor, options, shutdown, metrics, guard)
+                        }
+                    };
+
+                    match admission {
+                        // A delayed connection takes its place under
+                        // max_connections only once the delay is over,
+                        // so that idle connections cannot crowd out
+                        // clients that are within their rate limits.
+                        Admission::Delay(delay) => {
+                            drop(permit);
+                            let connection_limit = self.connection_limit.clone();
+                            let options = self.options.clone();
+                            let shutdown = self.shutdown.clone();
+                            let metrics = self.metrics.clone();
+                            task::spawn(async move {
+                                let delayed = async {
+                                    task::sleep(delay).await;
+                                    true
+                                };
+                                let shut_down = async {
+                                    shutdown.wait().await;
+                                    false
+                                };
+                                if !delayed.or(shut_down).await {
+                                    return;
+                                }
+
+                                let permit = match &connection_limit {
+                                    Some(connection_limit) => {
+                                        delayed_permit(
+                                            connection_limit,
+                                            &options,
+                                            &shutdown,
+                                            &metrics,
+                                        )
+                                        .await
+                                    }
+                                    None => None,
+                                };
+                                if connection_limit.is_some() && permit.is_none() {
+                                    return;
+                                }
+                                handle(permit);
+                            });
+                        }
+
+                        _ => handle(permit),
+                    }
                 }
             };
         }
+
+        drop(drain);
+        self.drain(connections).await;
         Ok(())
     }
 
-    fn info(&self) -> Vec<ListenInfo> {
-        vec![ListenInfo::new(
-            self.connection.to_string(),
-            String::from("tcp"),
-            true,
-        )]
+    /// Accepts plaintext http connections until the listener is shut
+    /// down, redirecting each of their requests to https.
+    async fn accept_http_redirect(&self) -> io::Result<()> {
+        let listener = match &self.http_redirect {
+            Some(TcpConnection::Connected(tcp)) => tcp,
+            _ => return Ok(()),
+        };
+        let https_port = self.connection.port().unwrap_or(443);
+        let mut incoming = listener.incoming();
+
+        loop {
+            let stream = incoming
+                .next()
+                .or(async {
+                    self.shutdown.wait().await;
+                    None
+                })
+                .await;
+
+            match stream {
+                None => return Ok(()),
+
+                Some(Err(ref e)) if is_transient_error(e) => continue,
+
+                Some(Err(error)) => {
+                    let delay = Duration::from_millis(500);
+                    tide::log::error!("Error: {}. Pausing for {:?}.", error, delay);
+                    task::sleep(delay).await;
+                }
+
+                Some(Ok(stream)) => {
+                    let shutdown = self.shutdown.clone();
+                    task::spawn(async move {
+                        http_redirect::serve(stream, https_port, &shutdown).await;
+                    });
+                }
+            }
+        }
     }
 }
 
+/// Certs are named after the file they were loaded from in expiry
+/// warnings.
+fn cert_name(cert: &CertSource) -> String {
+    cert.path()
+        .map_or_else(|| String::from("cert"), |path| path.display().to_string())
+}
+
 fn is_transient_error(e: &io::Error) -> bool {
     use io::ErrorKind::*;
     matches!(
@@ -253,26 +948,231 @@ impl<State> Display for TlsListener<State> {
     }
 }
 
-fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
-    certs(&mut BufReader::new(File::open(path)?))
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))
-}
+#[cfg(test)]
+mod tests {
+    use super::{TlsListener, TlsListenerBuilder};
+    use crate::{
+        ConnectionEvent, ConnectionEventKind, ConnectionLimitBehavior, PlaintextHttp, RateLimit,
+        RateLimitBehavior, ShutdownHandle,
+    };
 
-fn load_keys(path: &Path) -> io::Result<Vec<PrivateKey>> {
-    let mut bufreader = BufReader::new(File::open(path)?);
-    if let Ok(pkcs8) = pkcs8_private_keys(&mut bufreader) {
-        if !pkcs8.is_empty() {
-            return Ok(pkcs8);
-        }
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::prelude::*;
+    use async_std::task;
+
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const CA: &[u8] = include_bytes!("../tests/fixtures/ca.pem");
+    const CERT: &str = "tests/fixtures/rsa.pem";
+    const KEY: &str = "tests/fixtures/rsa-pkcs8.key";
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+    /// Serves a tide app that answers `/` with "hello tls" on a local
+    /// port, returning the address and a handle to stop the listener.
+    async fn serve(builder: TlsListenerBuilder<()>) -> (SocketAddr, ShutdownHandle) {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let listener = builder.tcp(tcp).cert(CERT).key(KEY).finish().unwrap();
+        let shutdown = listener.shutdown_handle();
+
+        let mut app = tide::new();
+        app.at("/").get(|_| async { Ok("hello tls") });
+        task::spawn(app.listen(listener));
+        (addr, shutdown)
+    }
+
+    /// Sends `request` over TLS and returns the raw http response.
+    async fn tls_request(addr: SocketAddr, request: &[u8]) -> String {
+        tls_request_on(TcpStream::connect(addr).await.unwrap(), request).await
+    }
+
+    async fn tls_request_on(tcp: TcpStream, request: &[u8]) -> String {
+        let mut config = rustls::ClientConfig::new();
+        config.root_store.add_pem_file(&mut &*CA).unwrap();
+        let connector = async_rustls::TlsConnector::from(Arc::new(config));
+
+        let name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let mut stream = connector.connect(name, tcp).await.unwrap();
+        stream.write_all(request).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
     }
 
-    bufreader.seek(SeekFrom::Start(0))?;
+    /// Sends `request` without TLS and returns the raw http response.
+    async fn plaintext_request(addr: SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn sniffs_tls_and_plaintext_on_the_same_port() {
+        task::block_on(async {
+            let builder = TlsListener::build().plaintext_http(PlaintextHttp::Redirect);
+            let (addr, shutdown) = serve(builder).await;
+
+            let response = tls_request(addr, REQUEST).await;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.ends_with("hello tls"), "{}", response);
+
+            let response = plaintext_request(addr, REQUEST).await;
+            assert!(
+                response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+                "{}",
+                response
+            );
+            let location = format!("location: https://localhost:{}/\r\n", addr.port());
+            assert!(response.contains(&location), "{}", response);
 
-    if let Ok(rsa) = rsa_private_keys(&mut bufreader) {
-        if !rsa.is_empty() {
-            return Ok(rsa);
+            shutdown.shutdown();
+        });
+    }
+
+    #[test]
+    fn serves_plaintext_with_the_app() {
+        task::block_on(async {
+            let builder = TlsListener::build().plaintext_http(PlaintextHttp::Serve);
+            let (addr, shutdown) = serve(builder).await;
+
+            let response = plaintext_request(addr, REQUEST).await;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.ends_with("hello tls"), "{}", response);
+
+            shutdown.shutdown();
+        });
+    }
+
+    /// Collects the events a listener emits, for
+    /// [`TlsListenerBuilder::connection_observer`].
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<ConnectionEvent>>>);
+
+    impl Events {
+        fn observe(&self, builder: TlsListenerBuilder<()>) -> TlsListenerBuilder<()> {
+            let events = self.0.clone();
+            builder.connection_observer(move |event: &ConnectionEvent| {
+                events.lock().unwrap().push(event.clone());
+            })
+        }
+
+        /// Waits for `count` connections to close, then returns every
+        /// event.
+        async fn closed(&self, count: usize) -> Vec<ConnectionEvent> {
+            for _ in 0..500 {
+                let events = self.0.lock().unwrap().clone();
+                let closed = events
+                    .iter()
+                    .filter(|event| matches!(event.kind(), ConnectionEventKind::Closed { .. }))
+                    .count();
+                if closed >= count {
+                    return events;
+                }
+                task::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("connections did not close");
         }
     }
 
-    Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid key"))
+    #[test]
+    fn events_for_a_served_connection() {
+        task::block_on(async {
+            let events = Events::default();
+            let (addr, shutdown) = serve(events.observe(TlsListener::build())).await;
+
+            let response = tls_request(addr, REQUEST).await;
+            let events = events.closed(1).await;
+            shutdown.shutdown();
+
+            assert!(events.iter().all(|event| event.id() == events[0].id()));
+            assert!(events
+                .iter()
+                .all(|event| event.peer_addr().map(|addr| addr.ip()) == Some(addr.ip())));
+
+            let kinds: Vec<_> = events.iter().map(ConnectionEvent::kind).collect();
+            assert_eq!(kinds.len(), 3, "{:?}", kinds);
+            assert!(matches!(kinds[0], ConnectionEventKind::Accepted));
+            assert!(matches!(
+                kinds[1],
+                ConnectionEventKind::HandshakeCompleted { .. }
+            ));
+            match kinds[2] {
+                ConnectionEventKind::Closed {
+                    bytes_read,
+                    bytes_written,
+                    ..
+                } => {
+                    assert_eq!(*bytes_read, REQUEST.len() as u64);
+                    assert_eq!(*bytes_written, response.len() as u64);
+                }
+                other => panic!("expected the connection to close, got {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn rejected_connections_are_counted() {
+        task::block_on(async {
+            let listener = TlsListener::build()
+                .connection_rate_limit(RateLimit::new(1, Duration::from_secs(60 * 60)));
+            let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = tcp.local_addr().unwrap();
+            let listener = listener.tcp(tcp).cert(CERT).key(KEY).finish().unwrap();
+            let metrics = listener.metrics();
+            let shutdown = listener.shutdown_handle();
+            task::spawn(tide::new().listen(listener));
+
+            tls_request(addr, REQUEST).await;
+            let mut rejected = TcpStream::connect(addr).await.unwrap();
+            let mut received = Vec::new();
+            rejected.read_to_end(&mut received).await.unwrap();
+            assert!(received.is_empty());
+            shutdown.shutdown();
+
+            let snapshot = metrics.snapshot();
+            assert_eq!(snapshot.accepted_connections(), 1);
+            assert_eq!(snapshot.rate_limited_connections(), 1);
+            assert_eq!(snapshot.connections_over_limit(), 0);
+        });
+    }
+
+    #[test]
+    fn delayed_connections_take_no_connection_slot() {
+        task::block_on(async {
+            let builder = TlsListener::build()
+                .max_connections(1)
+                .connection_limit_behavior(ConnectionLimitBehavior::Close)
+                .connection_rate_limit(RateLimit::new(1, Duration::from_secs(1)))
+                .rate_limit_behavior(RateLimitBehavior::Delay);
+            let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = tcp.local_addr().unwrap();
+            let listener = builder.tcp(tcp).cert(CERT).key(KEY).finish().unwrap();
+            let active_connections = listener.active_connections();
+            let metrics = listener.metrics();
+            let shutdown = listener.shutdown_handle();
+            let mut app = tide::new();
+            app.at("/").get(|_| async { Ok("hello tls") });
+            task::spawn(app.listen(listener));
+
+            tls_request(addr, REQUEST).await;
+            while active_connections.count() > 0 {
+                task::sleep(Duration::from_millis(10)).await;
+            }
+
+            let delayed = TcpStream::connect(addr).await.unwrap();
+            task::sleep(Duration::from_millis(200)).await;
+            assert_eq!(active_connections.count(), 0);
+
+            let response = tls_request_on(delayed, REQUEST).await;
+            assert!(response.ends_with("hello tls"), "{}", response);
+            assert_eq!(metrics.snapshot().connections_over_limit(), 0);
+            shutdown.shutdown();
+        });
+    }
 }
@@ -1,14 +1,27 @@
 use async_std::io;
 use async_std::net::TcpListener;
 
-use rustls::ServerConfig;
+use rustls::{CipherSuite, ProducesTickets, ProtocolVersion, ServerConfig};
 
-use super::{CustomTlsAcceptor, TcpConnection, TlsListener, TlsListenerConfig};
+use super::certificates::{load_certified_key, CertSource, KeySource};
+use super::key_log::KeyLogDestination;
+use super::ocsp::OcspSource;
+use super::server_config_options::ClientAuth;
+#[cfg(feature = "acme")]
+use super::AcmeConfig;
+#[cfg(feature = "self-signed")]
+use super::SelfSignedCert;
+use super::{
+    ConnectionLimitBehavior, ConnectionObserver, CustomTlsAcceptor, ListenerOptions, PlaintextHttp,
+    ProxyProtocol, RateLimit, RateLimitBehavior, ServerConfigOptions, TcpConnection, TicketKeys,
+    TlsListener, TlsListenerConfig, TlsPolicy,
+};
 
 use std::marker::PhantomData;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// # A builder for TlsListeners
 ///
@@ -30,6 +43,68 @@ use std::sync::Arc;
 ///
 /// ```rust
 /// # use tide_rustls::TlsListener;
+/// # use std::time::Duration;
+/// let listener = TlsListener::<()>::build()
+///     .addrs("localhost:4433")
+///     .cert("./tls/localhost-4433.cert")
+///     .key("./tls/localhost-4433.key")
+///     .cert_reload_interval(Duration::from_secs(60))
+///     .finish();
+/// ```
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
+/// let listener = TlsListener::<()>::build()
+///     .addrs("localhost:4433")
+///     .cert("./tls/localhost-4433.cert")
+///     .key("./tls/localhost-4433.key")
+///     .ocsp_fetch()
+///     .finish();
+/// ```
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
+/// let listener = TlsListener::<()>::build()
+///     .addrs("0.0.0.0:443")
+///     .sni_cert("example.com", "./tls/example.com.cert", "./tls/example.com.key")
+///     .sni_cert("*.example.org", "./tls/example.org.cert", "./tls/example.org.key")
+///     .cert("./tls/default.cert")
+///     .key("./tls/default.key")
+///     .finish();
+/// ```
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
+/// let listener = TlsListener::<()>::build()
+///     .addrs("0.0.0.0:443")
+///     .cert("./tls/example.com.cert")
+///     .key("./tls/example.com.key")
+///     .http_redirect("0.0.0.0:80")
+///     .finish();
+/// ```
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
+/// let listener = TlsListener::<()>::build()
+///     .addrs("localhost:4433")
+///     .cert("./tls/localhost-4433.cert")
+///     .key("./tls/localhost-4433.key")
+///     .client_auth_required("./tls/client-ca.cert")
+///     .finish();
+/// ```
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
+/// # let (cert_pem, key_pem) = (vec![], vec![]);
+/// let listener = TlsListener::<()>::build()
+///     .addrs("localhost:4433")
+///     .cert_pem(cert_pem)
+///     .key_pem(key_pem)
+///     .finish();
+/// ```
+///
+/// ```rust
+/// # use tide_rustls::TlsListener;
 /// let listener = TlsListener::<()>::build()
 ///     .tcp(std::net::TcpListener::bind("localhost:4433").unwrap())
 ///     .config(rustls::ServerConfig::new(rustls::NoClientAuth::new()))
@@ -44,18 +119,27 @@ use std::sync::Arc;
 ///     .key("./tls/localhost-4433.key")
 ///     .tcp_ttl(60)
 ///     .tcp_nodelay(true)
+///     .handshake_timeout(std::time::Duration::from_secs(10))
+///     .max_connections(10_000)
 ///     .finish();
 /// ```
-
 pub struct TlsListenerBuilder<State> {
-    key: Option<PathBuf>,
-    cert: Option<PathBuf>,
+    key: Option<KeySource>,
+    cert: Option<CertSource>,
+    cert_reload_interval: Option<Duration>,
+    ocsp: Option<OcspSource>,
+    sni_certs: Vec<(String, PathBuf, PathBuf)>,
+    server_config_options: ServerConfigOptions,
     config: Option<ServerConfig>,
     tls_acceptor: Option<Arc<dyn CustomTlsAcceptor>>,
+    #[cfg(feature = "acme")]
+    acme: Option<AcmeConfig>,
+    #[cfg(feature = "self-signed")]
+    self_signed: Option<SelfSignedCert>,
     tcp: Option<TcpListener>,
     addrs: Option<Vec<SocketAddr>>,
-    tcp_nodelay: Option<bool>,
-    tcp_ttl: Option<u32>,
+    http_redirect: Option<Vec<SocketAddr>>,
+    listener_options: ListenerOptions,
     _state: PhantomData<State>,
 }
 
@@ -64,12 +148,20 @@ impl<State> Default for TlsListenerBuilder<State> {
         Self {
             key: None,
             cert: None,
+            cert_reload_interval: None,
+            ocsp: None,
+            sni_certs: Vec::new(),
+            server_config_options: ServerConfigOptions::default(),
             config: None,
             tls_acceptor: None,
+            #[cfg(feature = "acme")]
+            acme: None,
+            #[cfg(feature = "self-signed")]
+            self_signed: None,
             tcp: None,
             addrs: None,
-            tcp_nodelay: None,
-            tcp_ttl: None,
+            http_redirect: None,
+            listener_options: ListenerOptions::default(),
             _state: PhantomData,
         }
     }
@@ -77,9 +169,14 @@ impl<State> Default for TlsListenerBuilder<State> {
 
 impl<State> std::fmt::Debug for TlsListenerBuilder<State> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("TlsListenerBuilder")
+        let mut debug = f.debug_struct("TlsListenerBuilder");
+        debug
             .field("key", &self.key)
             .field("cert", &self.cert)
+            .field("cert_reload_interval", &self.cert_reload_interval)
+            .field("ocsp", &self.ocsp)
+            .field("sni_certs", &self.sni_certs)
+            .field("server_config_options", &self.server_config_options)
             .field(
                 "config",
                 &if self.config.is_some() {
@@ -98,9 +195,16 @@ impl<State> std::fmt::Debug for TlsListenerBuilder<State> {
             )
             .field("tcp", &self.tcp)
             .field("addrs", &self.addrs)
-            .field("tcp_nodelay", &self.tcp_nodelay)
-            .field("tcp_ttl", &self.tcp_ttl)
-            .finish()
+            .field("http_redirect", &self.http_redirect)
+            .field("listener_options", &self.listener_options);
+
+        #[cfg(feature = "acme")]
+        debug.field("acme", &self.acme);
+
+        #[cfg(feature = "self-signed")]
+        debug.field("self_signed", &self.self_signed);
+
+        debug.finish()
     }
 }
 
@@ -109,12 +213,31 @@ impl<State> TlsListenerBuilder<State> {
         Self::default()
     }
 
-    /// Provide a path to a key file, in either pkcs8 or rsa
-    /// formats. This is mutually exclusive with providing a server
+    /// Provide a path to a pem-encoded key file, in pkcs8 (rsa, ecdsa
+    /// or ed25519), pkcs1 (rsa) or sec1 (ecdsa) formats. If the file
+    /// contains several keys, the first one that can be used is
+    /// chosen. This is mutually exclusive with providing a server
     /// config with [`TlsListenerBuilder::config`], but must be used
     /// in conjunction with [`TlsListenerBuilder::cert`]
     pub fn key(mut self, path: impl AsRef<Path>) -> Self {
-        self.key = Some(path.as_ref().into());
+        self.key = Some(KeySource::Path(path.as_ref().into()));
+        self
+    }
+
+    /// Provide a pem-encoded key from memory, in any of the formats
+    /// accepted by [`TlsListenerBuilder::key`]. This takes the place
+    /// of [`TlsListenerBuilder::key`], and is subject to the same
+    /// requirements.
+    pub fn key_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.key = Some(KeySource::Pem(pem.into()));
+        self
+    }
+
+    /// Provide a der-encoded key from memory, in pkcs8, pkcs1 or sec1
+    /// format. This takes the place of [`TlsListenerBuilder::key`],
+    /// and is subject to the same requirements.
+    pub fn key_der(mut self, der: impl Into<Vec<u8>>) -> Self {
+        self.key = Some(KeySource::Der(der.into()));
         self
     }
 
@@ -123,7 +246,250 @@ impl<State> TlsListenerBuilder<State> {
     /// but must be used in conjunction with
     /// [`TlsListenerBuilder::key`]
     pub fn cert(mut self, path: impl AsRef<Path>) -> Self {
-        self.cert = Some(path.as_ref().into());
+        self.cert = Some(CertSource::Path(path.as_ref().into()));
+        self
+    }
+
+    /// Provide a pem-encoded cert chain from memory. This takes the
+    /// place of [`TlsListenerBuilder::cert`], and is subject to the
+    /// same requirements.
+    pub fn cert_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.cert = Some(CertSource::Pem(pem.into()));
+        self
+    }
+
+    /// Provide a cert chain from memory as der-encoded certificates,
+    /// starting with the end-entity certificate. This takes the place
+    /// of [`TlsListenerBuilder::cert`], and is subject to the same
+    /// requirements.
+    pub fn cert_der(mut self, chain: Vec<Vec<u8>>) -> Self {
+        self.cert = Some(CertSource::Der(chain));
+        self
+    }
+
+    /// Provide a cert file and key file to be served to clients that
+    /// request `hostname` through SNI. This can be called repeatedly to
+    /// serve several hostnames from one listener. A hostname of the
+    /// form `*.example.com` matches any single label in place of the
+    /// `*`, and is only used if no exact hostname matches.
+    ///
+    /// If [`TlsListenerBuilder::cert`] and [`TlsListenerBuilder::key`]
+    /// are also provided, they are served to any client that does not
+    /// match one of these hostnames. Otherwise, those handshakes are
+    /// aborted. This is mutually exclusive with
+    /// [`TlsListenerBuilder::config`] and
+    /// [`TlsListenerBuilder::tls_acceptor`].
+    pub fn sni_cert(
+        mut self,
+        hostname: impl Into<String>,
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Self {
+        self.sni_certs
+            .push((hostname.into(), cert.as_ref().into(), key.as_ref().into()));
+        self
+    }
+
+    /// Check the [`TlsListenerBuilder::cert`] and
+    /// [`TlsListenerBuilder::key`] files for changes at this interval,
+    /// and use the updated cert and key for any subsequent
+    /// handshakes. Connections that have already been established
+    /// are unaffected. If the new files cannot be loaded, the error
+    /// is logged and the previous cert and key continue to be
+    /// served. This can only be used in conjunction with
+    /// [`TlsListenerBuilder::cert`] and [`TlsListenerBuilder::key`]
+    /// files or [`TlsListenerBuilder::sni_cert`]s. A default cert and
+    /// key held in memory alongside sni certs cannot be reloaded,
+    /// which is logged when the listener is bound.
+    pub fn cert_reload_interval(mut self, interval: Duration) -> Self {
+        self.cert_reload_interval = Some(interval);
+        self
+    }
+
+    /// Staple the der-encoded OCSP response in this file to each
+    /// handshake, sparing clients a request to the certificate
+    /// authority to check whether the cert has been revoked. The
+    /// response must be for the [`TlsListenerBuilder::cert`], and for
+    /// its issuer if the chain includes it, and must not have expired.
+    /// A response saying the cert has been revoked is logged as a
+    /// warning and stapled all the same. It is reloaded along with the
+    /// cert and key when
+    /// [`TlsListenerBuilder::cert_reload_interval`] is set, so it can
+    /// be refreshed by an external job. This can only be used in
+    /// conjunction with [`TlsListenerBuilder::cert`] and
+    /// [`TlsListenerBuilder::key`].
+    pub fn ocsp_response(mut self, path: impl AsRef<Path>) -> Self {
+        self.ocsp = Some(OcspSource::Path(path.as_ref().into()));
+        self
+    }
+
+    /// Fetch an OCSP response in the background from the responder
+    /// named in the [`TlsListenerBuilder::cert`], and staple it to
+    /// each handshake once it arrives. A new response is fetched
+    /// halfway through the validity of the current one, and whenever
+    /// the cert is reloaded. The cert chain must include the
+    /// certificate that issued the cert. This is mutually exclusive
+    /// with [`TlsListenerBuilder::ocsp_response`], and can only be
+    /// used in conjunction with [`TlsListenerBuilder::cert`] and
+    /// [`TlsListenerBuilder::key`].
+    pub fn ocsp_fetch(mut self) -> Self {
+        self.ocsp = Some(OcspSource::Fetch(None));
+        self
+    }
+
+    /// Like [`TlsListenerBuilder::ocsp_fetch`], but fetch responses
+    /// from this http url instead of the responder named in the cert,
+    /// such as a caching proxy or a local stand-in for tests.
+    pub fn ocsp_responder(mut self, url: impl Into<String>) -> Self {
+        self.ocsp = Some(OcspSource::Fetch(Some(url.into())));
+        self
+    }
+
+    /// Require every client to present a certificate that chains up
+    /// to one of the certificates in the provided pem-encoded CA
+    /// bundle file. Connections without a valid client certificate
+    /// are rejected during the handshake. This is mutually exclusive
+    /// with [`TlsListenerBuilder::client_auth_optional`], and can only
+    /// be used in conjunction with [`TlsListenerBuilder::cert`] and
+    /// [`TlsListenerBuilder::key`] or
+    /// [`TlsListenerBuilder::sni_cert`].
+    pub fn client_auth_required(mut self, ca: impl AsRef<Path>) -> Self {
+        self.server_config_options
+            .set_client_auth(ClientAuth::Required(ca.as_ref().into()));
+        self
+    }
+
+    /// Request a certificate from every client, verifying it against
+    /// the provided pem-encoded CA bundle file if one is presented,
+    /// but also accepting clients that do not present a
+    /// certificate. This is mutually exclusive with
+    /// [`TlsListenerBuilder::client_auth_required`], and can only be
+    /// used in conjunction with [`TlsListenerBuilder::cert`] and
+    /// [`TlsListenerBuilder::key`] or
+    /// [`TlsListenerBuilder::sni_cert`].
+    pub fn client_auth_optional(mut self, ca: impl AsRef<Path>) -> Self {
+        self.server_config_options
+            .set_client_auth(ClientAuth::Optional(ca.as_ref().into()));
+        self
+    }
+
+    /// Append the secrets for each TLS session to this file in the NSS
+    /// key log format, so that tools such as Wireshark can decrypt
+    /// captured traffic when debugging client interoperability.
+    /// Anyone who can read the file can decrypt that traffic, so on
+    /// unix a new file is readable only by its owner, a warning is
+    /// logged when the listener is bound, and this must never be used
+    /// in production. This can only be used in
+    /// conjunction with [`TlsListenerBuilder::cert`] and
+    /// [`TlsListenerBuilder::key`], [`TlsListenerBuilder::sni_cert`],
+    /// `TlsListenerBuilder::acme` or `TlsListenerBuilder::self_signed`.
+    pub fn key_log_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.server_config_options.key_log = Some(KeyLogDestination::Path(path.as_ref().into()));
+        self
+    }
+
+    /// Like [`TlsListenerBuilder::key_log_file`], but write to the
+    /// file named by the `SSLKEYLOGFILE` environment variable when the
+    /// listener is bound, as browsers and curl do. Nothing is logged
+    /// if the variable is not set, so this can be left enabled in
+    /// development builds and turned on for a single run.
+    pub fn key_log_from_env(mut self) -> Self {
+        self.server_config_options.key_log = Some(KeyLogDestination::Env);
+        self
+    }
+
+    /// Only allow the TLS versions and cipher suites of this
+    /// [`TlsPolicy`], rather than everything rustls supports. This can
+    /// be narrowed further with [`TlsListenerBuilder::min_tls_version`],
+    /// [`TlsListenerBuilder::max_tls_version`] and
+    /// [`TlsListenerBuilder::cipher_suites`], and can only be used in
+    /// conjunction with [`TlsListenerBuilder::cert`] and
+    /// [`TlsListenerBuilder::key`], [`TlsListenerBuilder::sni_cert`],
+    /// `TlsListenerBuilder::acme` or `TlsListenerBuilder::self_signed`.
+    /// Binding the listener fails if a certificate's key cannot be used
+    /// with any of the cipher suites that remain.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use tide_rustls::{TlsListener, TlsPolicy};
+    /// let listener = TlsListener::<()>::build()
+    ///     .addrs("localhost:4433")
+    ///     .cert("./tls/localhost-4433.cert")
+    ///     .key("./tls/localhost-4433.key")
+    ///     .tls_policy(TlsPolicy::Modern)
+    ///     .finish();
+    /// ```
+    pub fn tls_policy(mut self, policy: TlsPolicy) -> Self {
+        self.server_config_options.protocols.policy = Some(policy);
+        self
+    }
+
+    /// Refuse handshakes with TLS versions older than this one, which
+    /// must be `TLSv1_2` or `TLSv1_3`. See
+    /// [`TlsListenerBuilder::tls_policy`] for the listeners this
+    /// applies to.
+    pub fn min_tls_version(mut self, version: ProtocolVersion) -> Self {
+        self.server_config_options.protocols.min_version = Some(version);
+        self
+    }
+
+    /// Refuse handshakes with TLS versions newer than this one, which
+    /// must be `TLSv1_2` or `TLSv1_3`. See
+    /// [`TlsListenerBuilder::tls_policy`] for the listeners this
+    /// applies to.
+    pub fn max_tls_version(mut self, version: ProtocolVersion) -> Self {
+        self.server_config_options.protocols.max_version = Some(version);
+        self
+    }
+
+    /// Only allow these cipher suites, in order of preference, instead
+    /// of those of the [`TlsPolicy`]. Suites for TLS versions that are
+    /// not allowed are ignored. See [`TlsListenerBuilder::tls_policy`]
+    /// for the listeners this applies to.
+    pub fn cipher_suites(mut self, cipher_suites: impl IntoIterator<Item = CipherSuite>) -> Self {
+        self.server_config_options.protocols.cipher_suites =
+            Some(cipher_suites.into_iter().collect());
+        self
+    }
+
+    /// Keep the secrets of up to this many TLS sessions in memory so
+    /// that clients can resume them, instead of the 256 that rustls
+    /// keeps by default. A size of zero keeps none, leaving only
+    /// [`TlsListenerBuilder::session_tickets`] for resumption. See
+    /// [`TlsListenerBuilder::tls_policy`] for the listeners this
+    /// applies to.
+    pub fn session_cache_size(mut self, size: usize) -> Self {
+        self.server_config_options.resumption.cache_size = Some(size);
+        self
+    }
+
+    /// Issue stateless session tickets encrypted with these
+    /// [`TicketKeys`], so that clients can resume sessions without the
+    /// listener storing anything. Clone the keys to share them between
+    /// listeners. See [`TlsListenerBuilder::tls_policy`] for the
+    /// listeners this applies to.
+    pub fn session_tickets(self, keys: TicketKeys) -> Self {
+        self.session_ticketer(Arc::new(keys))
+    }
+
+    /// Like [`TlsListenerBuilder::session_tickets`], but with any
+    /// [`ProducesTickets`] implementation, such as one that derives
+    /// its keys from a secret shared by every instance behind a load
+    /// balancer so that sessions can be resumed on any of them.
+    pub fn session_ticketer(mut self, ticketer: Arc<dyn ProducesTickets>) -> Self {
+        self.server_config_options.resumption.ticketer = Some(ticketer);
+        self
+    }
+
+    /// Do not let clients resume TLS sessions, so that every connection
+    /// performs a full handshake. This is mutually exclusive with
+    /// [`TlsListenerBuilder::session_cache_size`] and
+    /// [`TlsListenerBuilder::session_tickets`]. See
+    /// [`TlsListenerBuilder::tls_policy`] for the listeners this
+    /// applies to.
+    pub fn disable_session_resumption(mut self) -> Self {
+        self.server_config_options.resumption.disabled = true;
         self
     }
 
@@ -148,6 +514,35 @@ impl<State> TlsListenerBuilder<State> {
         self
     }
 
+    /// Obtain a certificate for the domains in `config` from an ACME
+    /// certificate authority such as Let's Encrypt, and keep it
+    /// renewed. The certificate is requested in the background once
+    /// the listener is bound, and handshakes fail until it has been
+    /// issued, unless a cached certificate is available. This is
+    /// mutually exclusive with [`TlsListenerBuilder::cert`],
+    /// [`TlsListenerBuilder::key`], [`TlsListenerBuilder::sni_cert`],
+    /// [`TlsListenerBuilder::config`] and
+    /// [`TlsListenerBuilder::tls_acceptor`]. This requires the `acme`
+    /// cargo feature.
+    #[cfg(feature = "acme")]
+    pub fn acme(mut self, config: AcmeConfig) -> Self {
+        self.acme = Some(config);
+        self
+    }
+
+    /// Serve a self-signed certificate for local development,
+    /// generated when the listener is bound. This is mutually
+    /// exclusive with [`TlsListenerBuilder::cert`],
+    /// [`TlsListenerBuilder::key`], [`TlsListenerBuilder::sni_cert`],
+    /// [`TlsListenerBuilder::config`] and
+    /// [`TlsListenerBuilder::tls_acceptor`]. This requires the
+    /// `self-signed` cargo feature.
+    #[cfg(feature = "self-signed")]
+    pub fn self_signed(mut self, cert: SelfSignedCert) -> Self {
+        self.self_signed = Some(cert);
+        self
+    }
+
     /// Provides a bound tcp listener (either async-std or std) to
     /// build this tls listener on. This is mutually exclusive with
     /// [`TlsListenerBuilder::addrs`], but one of them is mandatory.
@@ -166,18 +561,222 @@ impl<State> TlsListenerBuilder<State> {
         self
     }
 
+    /// Also listen for plaintext http on these addresses, answering
+    /// every request with a redirect to the same host and path on
+    /// this listener's https port. GET and HEAD requests are
+    /// redirected with `301 Moved Permanently`, and other methods
+    /// with `308 Permanent Redirect` so that clients repeat them with
+    /// the same method and body. The redirect listener stops along
+    /// with this one when it is shut down.
+    pub fn http_redirect(mut self, addrs: impl ToSocketAddrs) -> Self {
+        if let Ok(socket_addrs) = addrs.to_socket_addrs() {
+            self.http_redirect = Some(socket_addrs.collect());
+        }
+        self
+    }
+
     /// Provides a TCP_NODELAY option for this tls listener.
     pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
-        self.tcp_nodelay = Some(nodelay);
+        self.listener_options.tcp_nodelay = Some(nodelay);
         self
     }
 
     /// Provides a TTL option for this tls listener, in seconds.
     pub fn tcp_ttl(mut self, ttl: u32) -> Self {
-        self.tcp_ttl = Some(ttl);
+        self.listener_options.tcp_ttl = Some(ttl);
+        self
+    }
+
+    /// Provides a limit on how long a listener that has been shut
+    /// down with a [`ShutdownHandle`](crate::ShutdownHandle) waits for
+    /// in-flight requests to complete. Any connections still open
+    /// after this duration are closed without waiting for their
+    /// responses. Without a drain timeout, the listener waits for
+    /// every in-flight request to complete, however long that takes.
+    /// Connections that are idle between requests or that have not yet
+    /// completed their TLS handshake are closed as soon as the listener
+    /// shuts down either way.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.listener_options.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Provides a limit on how long a client may take to complete
+    /// the TLS handshake after opening a tcp connection. Connections
+    /// that exceed it are logged and closed. This applies to the
+    /// handshake performed by a
+    /// [`TlsListenerBuilder::tls_acceptor`] as well.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.listener_options.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Provides a limit on the number of connections this listener
+    /// serves at once, including connections that are still
+    /// performing a TLS handshake. What happens to connections beyond
+    /// this limit is determined by
+    /// [`TlsListenerBuilder::connection_limit_behavior`]. The limit
+    /// must be at least one.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.listener_options.max_connections = Some(max_connections);
         self
     }
 
+    /// Determines how this listener handles new connections once
+    /// [`TlsListenerBuilder::max_connections`] is reached. Defaults
+    /// to [`ConnectionLimitBehavior::Backpressure`].
+    pub fn connection_limit_behavior(mut self, behavior: ConnectionLimitBehavior) -> Self {
+        self.listener_options.connection_limit_behavior = behavior;
+        self
+    }
+
+    /// Limits how often each client ip address may open a connection.
+    /// This is checked as each connection is accepted, before any TLS
+    /// work is done for it, and what happens to connections beyond the
+    /// limit is determined by
+    /// [`TlsListenerBuilder::rate_limit_behavior`]. The address is
+    /// that of the tcp peer, so with
+    /// [`TlsListenerBuilder::proxy_protocol`] this limits the load
+    /// balancer as a whole rather than each client.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use tide_rustls::{RateLimit, RateLimitBehavior, TlsListener};
+    /// let listener = TlsListener::<()>::build()
+    ///     .addrs("localhost:4433")
+    ///     .cert("./tls/localhost-4433.cert")
+    ///     .key("./tls/localhost-4433.key")
+    ///     .connection_rate_limit(RateLimit::new(50, Duration::from_secs(10)))
+    ///     .failed_handshake_rate_limit(RateLimit::new(5, Duration::from_secs(60)))
+    ///     .rate_limit_behavior(RateLimitBehavior::Delay)
+    ///     .finish();
+    /// ```
+    pub fn connection_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.listener_options.rate_limiter.connections = Some(limit);
+        self
+    }
+
+    /// Limits how many failed TLS handshakes each client ip address may
+    /// cause, such as from scanners probing for protocol support or
+    /// clients that do not trust the certificate. Once an address runs
+    /// out, its new connections are handled according to
+    /// [`TlsListenerBuilder::rate_limit_behavior`] until it regains
+    /// one. See [`TlsListenerBuilder::connection_rate_limit`] for how
+    /// addresses are determined.
+    pub fn failed_handshake_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.listener_options.rate_limiter.failed_handshakes = Some(limit);
+        self
+    }
+
+    /// Determines how this listener handles new connections from an
+    /// address that has exceeded
+    /// [`TlsListenerBuilder::connection_rate_limit`] or
+    /// [`TlsListenerBuilder::failed_handshake_rate_limit`]. Defaults to
+    /// [`RateLimitBehavior::Close`].
+    pub fn rate_limit_behavior(mut self, behavior: RateLimitBehavior) -> Self {
+        self.listener_options.rate_limiter.behavior = behavior;
+        self
+    }
+
+    /// Expect a PROXY protocol header from a load balancer at the
+    /// start of each tcp connection, ahead of the TLS handshake, and
+    /// use the client address it conveys as the peer address of each
+    /// request. Connections with a malformed header are closed, as
+    /// are connections without one when the header is
+    /// [`ProxyProtocol::Required`]. Reading the header counts toward
+    /// the [`TlsListenerBuilder::handshake_timeout`].
+    pub fn proxy_protocol(mut self, proxy_protocol: ProxyProtocol) -> Self {
+        self.listener_options.proxy_protocol = Some(proxy_protocol);
+        self
+    }
+
+    /// Log a warning when a certificate this listener serves is
+    /// within any of these durations of expiring, and an error once it
+    /// has expired. Each warning is logged once per certificate, as
+    /// it passes each threshold, and certificates are checked when
+    /// the listener is bound and hourly after that. Defaults to 30, 7
+    /// and 1 days, and an empty list turns off the warnings but not
+    /// the error. The expiry dates themselves are available from
+    /// [`TlsListener::cert_expiry`](crate::TlsListener::cert_expiry).
+    pub fn cert_expiry_warnings(mut self, thresholds: impl IntoIterator<Item = Duration>) -> Self {
+        self.listener_options.cert_expiry_warnings = Some(thresholds.into_iter().collect());
+        self
+    }
+
+    /// Deliver an event to this observer at each stage of each
+    /// connection, from being accepted through the TLS handshake to
+    /// being closed. See [`ConnectionObserver`](crate::ConnectionObserver).
+    pub fn connection_observer(mut self, observer: impl ConnectionObserver) -> Self {
+        self.listener_options.connection_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Accept plaintext http on this listener's port as well as TLS,
+    /// telling them apart by the first byte each client sends, and
+    /// handle it as described by [`PlaintextHttp`]. When used with
+    /// [`TlsListenerBuilder::proxy_protocol`], this looks at the
+    /// first byte after the PROXY protocol header.
+    pub fn plaintext_http(mut self, plaintext_http: PlaintextHttp) -> Self {
+        self.listener_options.plaintext_http = Some(plaintext_http);
+        self
+    }
+
+    /// Serve http/2 to clients that support it. When enabled, the
+    /// listener advertises `h2` and `http/1.1` with ALPN, serves
+    /// connections that negotiate `h2` with an http/2 server, and
+    /// serves all others with http/1.1 as usual. A
+    /// [`TlsListenerBuilder::config`] that already specifies its own
+    /// `alpn_protocols` is left as it is, and a
+    /// [`TlsListenerBuilder::tls_acceptor`] is responsible for its own
+    /// ALPN negotiation. This requires the `http2` cargo feature.
+    #[cfg(feature = "http2")]
+    pub fn http2(mut self, enabled: bool) -> Self {
+        self.listener_options.http2 = enabled;
+        self
+    }
+
+    /// Loads the provided [`TlsListenerBuilder::cert`] and
+    /// [`TlsListenerBuilder::key`] and any
+    /// [`TlsListenerBuilder::sni_cert`]s, and checks that each key
+    /// belongs to its certificate, that each chain starts with the
+    /// end-entity certificate followed by its issuers in order, and
+    /// that the end-entity certificate has not expired and is already
+    /// valid. Issuers outside their validity period are only logged.
+    ///
+    /// The same checks are made when the listener is bound, so this is
+    /// only needed to find problems before then, such as at startup
+    /// before anything else is done. The error describes the first
+    /// problem found, naming the file it was found in.
+    ///
+    /// ```rust
+    /// # use tide_rustls::TlsListener;
+    /// let builder = TlsListener::<()>::build()
+    ///     .addrs("localhost:4433")
+    ///     .cert("./tls/localhost-4433.pem")
+    ///     .key("./tls/localhost-4433.key");
+    ///
+    /// if let Err(error) = builder.validate() {
+    ///     eprintln!("unable to use tls certificate: {}", error);
+    /// }
+    /// ```
+    pub fn validate(&self) -> io::Result<()> {
+        if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+            load_certified_key(cert, key)?;
+        }
+
+        for (hostname, cert, key) in &self.sni_certs {
+            load_certified_key(
+                &CertSource::Path(cert.clone()),
+                &KeySource::Path(key.clone()),
+            )
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", hostname, err)))?;
+        }
+
+        Ok(())
+    }
+
     /// finishes building a TlsListener from this TlsListenerBuilder.
     ///
     /// # Errors
@@ -186,46 +785,329 @@ impl<State> TlsListenerBuilder<State> {
     /// * either of these is provided, but not both
     ///   * [`TlsListenerBuilder::tcp`]
     ///   * [`TlsListenerBuilder::addrs`]
-    /// * exactly one of these is provided
+    /// * exactly one of these is provided (where
+    ///   [`TlsListenerBuilder::cert_pem`] or
+    ///   [`TlsListenerBuilder::cert_der`] may stand in for
+    ///   [`TlsListenerBuilder::cert`], and
+    ///   [`TlsListenerBuilder::key_pem`] or
+    ///   [`TlsListenerBuilder::key_der`] for
+    ///   [`TlsListenerBuilder::key`])
     ///   * both [`TlsListenerBuilder::cert`] AND [`TlsListenerBuilder::key`]
+    ///   * one or more [`TlsListenerBuilder::sni_cert`]s, optionally
+    ///     with both [`TlsListenerBuilder::cert`] AND
+    ///     [`TlsListenerBuilder::key`]
     ///   * [`TlsListenerBuilder::config`]
     ///   * [`TlsListenerBuilder::tls_acceptor`]
+    ///   * `TlsListenerBuilder::acme`, with at least one domain
+    ///   * `TlsListenerBuilder::self_signed`, with at least one name
+    /// * [`TlsListenerBuilder::cert_reload_interval`] is only provided
+    ///   along with [`TlsListenerBuilder::cert`] and
+    ///   [`TlsListenerBuilder::key`] files or
+    ///   [`TlsListenerBuilder::sni_cert`]
+    /// * [`TlsListenerBuilder::ocsp_response`],
+    ///   [`TlsListenerBuilder::ocsp_fetch`] and
+    ///   [`TlsListenerBuilder::ocsp_responder`] are only provided along
+    ///   with [`TlsListenerBuilder::cert`] and
+    ///   [`TlsListenerBuilder::key`], without any
+    ///   [`TlsListenerBuilder::sni_cert`]s
+    /// * [`TlsListenerBuilder::client_auth_required`],
+    ///   [`TlsListenerBuilder::client_auth_optional`],
+    ///   [`TlsListenerBuilder::key_log_file`],
+    ///   [`TlsListenerBuilder::key_log_from_env`],
+    ///   [`TlsListenerBuilder::tls_policy`],
+    ///   [`TlsListenerBuilder::min_tls_version`],
+    ///   [`TlsListenerBuilder::max_tls_version`],
+    ///   [`TlsListenerBuilder::cipher_suites`],
+    ///   [`TlsListenerBuilder::session_cache_size`],
+    ///   [`TlsListenerBuilder::session_tickets`],
+    ///   [`TlsListenerBuilder::session_ticketer`] and
+    ///   [`TlsListenerBuilder::disable_session_resumption`] are only
+    ///   provided along with [`TlsListenerBuilder::cert`] and
+    ///   [`TlsListenerBuilder::key`], [`TlsListenerBuilder::sni_cert`],
+    ///   `TlsListenerBuilder::acme` or
+    ///   `TlsListenerBuilder::self_signed`
     pub fn finish(self) -> io::Result<TlsListener<State>> {
         let Self {
             key,
             cert,
+            cert_reload_interval,
+            ocsp,
+            sni_certs,
+            server_config_options,
             config,
             tls_acceptor,
+            #[cfg(feature = "acme")]
+            acme,
+            #[cfg(feature = "self-signed")]
+            self_signed,
             tcp,
             addrs,
-            tcp_nodelay,
-            tcp_ttl,
+            http_redirect,
+            listener_options,
             ..
         } = self;
 
-        let config = match (key, cert, config, tls_acceptor) {
-            (Some(key), Some(cert), None, None) => TlsListenerConfig::Paths { key, cert },
-            (None, None, Some(config), None) => TlsListenerConfig::ServerConfig(config),
-            (None, None, None, Some(tls_acceptor)) => TlsListenerConfig::Acceptor(tls_acceptor),
+        let connection = match (tcp, addrs) {
+            (Some(tcp), None) => TcpConnection::Connected(tcp),
+            (None, Some(addrs)) => TcpConnection::Addrs(addrs),
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
-                    "need exactly one of cert + key, ServerConfig, or TLS acceptor",
+                    "either tcp or addrs are required",
                 ))
             }
         };
+        let http_redirect = http_redirect.map(TcpConnection::Addrs);
 
-        let connection = match (tcp, addrs) {
-            (Some(tcp), None) => TcpConnection::Connected(tcp),
-            (None, Some(addrs)) => TcpConnection::Addrs(addrs),
+        if listener_options.max_connections == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "max_connections must be at least one",
+            ));
+        }
+
+        listener_options.rate_limiter.check()?;
+
+        if ocsp.is_some() && (key.is_none() || cert.is_none() || !sni_certs.is_empty()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ocsp stapling requires cert + key without sni certs",
+            ));
+        }
+
+        if !server_config_options.protocols.is_default() {
+            server_config_options.protocols.resolve()?;
+        }
+
+        if let Err(message) = server_config_options.check() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+
+        #[cfg(feature = "self-signed")]
+        if let Some(self_signed) = self_signed {
+            #[cfg(feature = "acme")]
+            let uses_acme = acme.is_some();
+            #[cfg(not(feature = "acme"))]
+            let uses_acme = false;
+
+            if key.is_some()
+                || cert.is_some()
+                || !sni_certs.is_empty()
+                || config.is_some()
+                || tls_acceptor.is_some()
+                || uses_acme
+                || cert_reload_interval.is_some()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "self-signed cert cannot be combined with cert + key, sni certs, ServerConfig, TLS acceptor, or acme",
+                ));
+            }
+
+            if self_signed.names().is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "self-signed cert requires at least one name",
+                ));
+            }
+
+            let config = TlsListenerConfig::SelfSigned {
+                cert: self_signed,
+                options: server_config_options,
+            };
+            return Ok(TlsListener::new(
+                connection,
+                http_redirect,
+                config,
+                listener_options,
+            ));
+        }
+
+        #[cfg(feature = "acme")]
+        if let Some(acme) = acme {
+            if key.is_some()
+                || cert.is_some()
+                || !sni_certs.is_empty()
+                || config.is_some()
+                || tls_acceptor.is_some()
+                || cert_reload_interval.is_some()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "acme cannot be combined with cert + key, sni certs, ServerConfig, or TLS acceptor",
+                ));
+            }
+
+            if acme.domains.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "acme requires at least one domain",
+                ));
+            }
+
+            let config = TlsListenerConfig::Acme {
+                acme,
+                options: server_config_options,
+            };
+            return Ok(TlsListener::new(
+                connection,
+                http_redirect,
+                config,
+                listener_options,
+            ));
+        }
+
+        let files = matches!(
+            (&key, &cert),
+            (Some(KeySource::Path(_)), Some(CertSource::Path(_)))
+        );
+
+        if cert_reload_interval.is_some() && !files && sni_certs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cert_reload_interval requires cert + key files or sni certs",
+            ));
+        }
+
+        if let Some(option) = server_config_options.configured() {
+            if (key.is_none() || cert.is_none()) && sni_certs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} requires cert + key or sni certs", option),
+                ));
+            }
+        }
+
+        let config = match (key, cert, config, tls_acceptor) {
+            (Some(key), Some(cert), None, None) if !sni_certs.is_empty() => {
+                TlsListenerConfig::Sni {
+                    certs: sni_certs,
+                    default: Some((cert, key)),
+                    reload_interval: cert_reload_interval,
+                    options: server_config_options,
+                }
+            }
+            (None, None, None, None) if !sni_certs.is_empty() => TlsListenerConfig::Sni {
+                certs: sni_certs,
+                default: None,
+                reload_interval: cert_reload_interval,
+                options: server_config_options,
+            },
+            (Some(_), None, None, None) if !sni_certs.is_empty() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a default key for sni certs also requires a cert",
+                ))
+            }
+            (None, Some(_), None, None) if !sni_certs.is_empty() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a default cert for sni certs also requires a key",
+                ))
+            }
+            _ if !sni_certs.is_empty() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "sni certs cannot be combined with ServerConfig or TLS acceptor",
+                ))
+            }
+            (Some(key), Some(cert), None, None) => TlsListenerConfig::CertAndKey {
+                key,
+                cert,
+                reload_interval: cert_reload_interval,
+                ocsp,
+                options: server_config_options,
+            },
+            (None, None, Some(config), None) => TlsListenerConfig::ServerConfig(config),
+            (None, None, None, Some(tls_acceptor)) => TlsListenerConfig::Acceptor(tls_acceptor),
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
-                    "either tcp or addrs are required",
+                    "need exactly one of cert + key, ServerConfig, or TLS acceptor",
                 ))
             }
         };
 
-        Ok(TlsListener::new(connection, config, tcp_nodelay, tcp_ttl))
+        Ok(TlsListener::new(
+            connection,
+            http_redirect,
+            config,
+            listener_options,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finish_error(builder: TlsListenerBuilder<()>) -> String {
+        match builder.finish() {
+            Ok(_) => panic!("expected finish to fail"),
+            Err(error) => {
+                assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+                error.to_string()
+            }
+        }
+    }
+
+    fn sni_builder() -> TlsListenerBuilder<()> {
+        TlsListenerBuilder::new().addrs("localhost:4433").sni_cert(
+            "example.com",
+            "example.cert",
+            "example.key",
+        )
+    }
+
+    #[test]
+    fn sni_certs_with_only_a_default_cert() {
+        assert_eq!(
+            finish_error(sni_builder().cert("default.cert")),
+            "a default cert for sni certs also requires a key"
+        );
+    }
+
+    #[test]
+    fn sni_certs_with_only_a_default_key() {
+        assert_eq!(
+            finish_error(sni_builder().key("default.key")),
+            "a default key for sni certs also requires a cert"
+        );
+    }
+
+    #[test]
+    fn sni_certs_with_a_server_config() {
+        let config = ServerConfig::new(rustls::NoClientAuth::new());
+        assert_eq!(
+            finish_error(sni_builder().config(config)),
+            "sni certs cannot be combined with ServerConfig or TLS acceptor"
+        );
+    }
+
+    #[test]
+    fn zero_max_connections() {
+        let builder = TlsListenerBuilder::new()
+            .addrs("localhost:4433")
+            .cert("localhost.cert")
+            .key("localhost.key")
+            .max_connections(0);
+        assert_eq!(
+            finish_error(builder),
+            "max_connections must be at least one"
+        );
+    }
+
+    #[test]
+    fn both_client_auth_modes() {
+        let builder = TlsListenerBuilder::new()
+            .addrs("localhost:4433")
+            .cert("localhost.cert")
+            .key("localhost.key")
+            .client_auth_required("ca.cert")
+            .client_auth_optional("ca.cert");
+        assert_eq!(
+            finish_error(builder),
+            "client_auth_required and client_auth_optional are mutually exclusive"
+        );
     }
 }
@@ -2,21 +2,47 @@ use std::fmt::{self, Debug, Formatter};
 
 use rustls::ServerConfig;
 
-use super::CustomTlsAcceptor;
+use super::certificates::{CertSource, KeySource};
+use super::ocsp::OcspSource;
+#[cfg(feature = "acme")]
+use super::AcmeConfig;
+#[cfg(feature = "self-signed")]
+use super::SelfSignedCert;
+use super::{CustomTlsAcceptor, ServerConfigOptions};
 
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-impl Default for TlsListenerConfig {
-    fn default() -> Self {
-        Self::Unconfigured
-    }
-}
+#[derive(Default)]
 pub(crate) enum TlsListenerConfig {
+    #[default]
     Unconfigured,
     Acceptor(Arc<dyn CustomTlsAcceptor>),
     ServerConfig(ServerConfig),
-    Paths { cert: PathBuf, key: PathBuf },
+    CertAndKey {
+        cert: CertSource,
+        key: KeySource,
+        reload_interval: Option<Duration>,
+        ocsp: Option<OcspSource>,
+        options: ServerConfigOptions,
+    },
+    Sni {
+        certs: Vec<(String, PathBuf, PathBuf)>,
+        default: Option<(CertSource, KeySource)>,
+        reload_interval: Option<Duration>,
+        options: ServerConfigOptions,
+    },
+    #[cfg(feature = "acme")]
+    Acme {
+        acme: AcmeConfig,
+        options: ServerConfigOptions,
+    },
+    #[cfg(feature = "self-signed")]
+    SelfSigned {
+        cert: SelfSignedCert,
+        options: ServerConfigOptions,
+    },
 }
 
 impl Debug for TlsListenerConfig {
@@ -25,10 +51,43 @@ impl Debug for TlsListenerConfig {
             Self::Unconfigured => write!(f, "TlsListenerConfig::Unconfigured"),
             Self::Acceptor(_) => write!(f, "TlsListenerConfig::Acceptor(..)"),
             Self::ServerConfig(_) => write!(f, "TlsListenerConfig::ServerConfig(..)"),
-            Self::Paths { cert, key } => f
-                .debug_struct("TlsListenerConfig::Paths")
+            Self::CertAndKey {
+                cert,
+                key,
+                reload_interval,
+                ocsp,
+                options,
+            } => f
+                .debug_struct("TlsListenerConfig::CertAndKey")
                 .field("cert", cert)
                 .field("key", key)
+                .field("reload_interval", reload_interval)
+                .field("ocsp", ocsp)
+                .field("options", options)
+                .finish(),
+            Self::Sni {
+                certs,
+                default,
+                reload_interval,
+                options,
+            } => f
+                .debug_struct("TlsListenerConfig::Sni")
+                .field("certs", certs)
+                .field("default", default)
+                .field("reload_interval", reload_interval)
+                .field("options", options)
+                .finish(),
+            #[cfg(feature = "acme")]
+            Self::Acme { acme, options } => f
+                .debug_struct("TlsListenerConfig::Acme")
+                .field("acme", acme)
+                .field("options", options)
+                .finish(),
+            #[cfg(feature = "self-signed")]
+            Self::SelfSigned { cert, options } => f
+                .debug_struct("TlsListenerConfig::SelfSigned")
+                .field("cert", cert)
+                .field("options", options)
                 .finish(),
         }
     }
@@ -0,0 +1,389 @@
+use async_std::io;
+
+use rustls::internal::msgs::enums::SignatureAlgorithm;
+use rustls::sign::SigningKey;
+use rustls::{CipherSuite, ProtocolVersion, ServerConfig, SupportedCipherSuite};
+
+/// Named sets of TLS versions and cipher suites for
+/// [`TlsListenerBuilder::tls_policy`](crate::TlsListenerBuilder::tls_policy),
+/// following the server side TLS recommendations at
+/// <https://wiki.mozilla.org/Security/Server_Side_TLS>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsPolicy {
+    /// TLS 1.3 only, for services whose clients are all recent. This
+    /// works with any key type.
+    Modern,
+
+    /// TLS 1.2 and 1.3, with only forward-secret AEAD cipher suites,
+    /// for general purpose servers. This is what rustls enables by
+    /// default, ordered as Mozilla recommends.
+    Intermediate,
+}
+
+impl TlsPolicy {
+    fn versions(self) -> Vec<ProtocolVersion> {
+        match self {
+            Self::Modern => vec![ProtocolVersion::TLSv1_3],
+            Self::Intermediate => vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+        }
+    }
+
+    fn cipher_suites(self) -> Vec<CipherSuite> {
+        let tls13 = [
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+            CipherSuite::TLS13_AES_256_GCM_SHA384,
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+        ];
+
+        match self {
+            Self::Modern => tls13.to_vec(),
+            Self::Intermediate => tls13
+                .iter()
+                .copied()
+                .chain(vec![
+                    CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                    CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                    CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                    CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                    CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                    CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+                ])
+                .collect(),
+        }
+    }
+}
+
+/// The TLS versions and cipher suites chosen with
+/// [`TlsListenerBuilder::tls_policy`](crate::TlsListenerBuilder::tls_policy),
+/// [`TlsListenerBuilder::min_tls_version`](crate::TlsListenerBuilder::min_tls_version),
+/// [`TlsListenerBuilder::max_tls_version`](crate::TlsListenerBuilder::max_tls_version)
+/// and
+/// [`TlsListenerBuilder::cipher_suites`](crate::TlsListenerBuilder::cipher_suites).
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ProtocolOptions {
+    pub(crate) policy: Option<TlsPolicy>,
+    pub(crate) min_version: Option<ProtocolVersion>,
+    pub(crate) max_version: Option<ProtocolVersion>,
+    pub(crate) cipher_suites: Option<Vec<CipherSuite>>,
+}
+
+impl ProtocolOptions {
+    pub(crate) fn is_default(&self) -> bool {
+        self.policy.is_none()
+            && self.min_version.is_none()
+            && self.max_version.is_none()
+            && self.cipher_suites.is_none()
+    }
+
+    /// Restricts `config` to these versions and cipher suites, or
+    /// explains why no handshake could succeed with them.
+    pub(crate) fn apply(&self, config: &mut ServerConfig) -> io::Result<()> {
+        if self.is_default() {
+            return Ok(());
+        }
+
+        let (versions, cipher_suites) = self.resolve()?;
+        config.versions = versions;
+        config.ciphersuites = cipher_suites;
+        Ok(())
+    }
+
+    /// The protocol versions and cipher suites to enable, in order of
+    /// preference.
+    pub(crate) fn resolve(
+        &self,
+    ) -> io::Result<(Vec<ProtocolVersion>, Vec<&'static SupportedCipherSuite>)> {
+        let policy = self.policy.unwrap_or(TlsPolicy::Intermediate);
+
+        for version in self.min_version.iter().chain(&self.max_version) {
+            if !SUPPORTED_VERSIONS.contains(version) {
+                return Err(invalid_input(format!(
+                    "unsupported tls version {:?}; rustls supports TLSv1_2 and TLSv1_3",
+                    version
+                )));
+            }
+        }
+
+        let versions: Vec<ProtocolVersion> = policy
+            .versions()
+            .into_iter()
+            .filter(|version| {
+                self.min_version
+                    .map_or(true, |min| version.get_u16() >= min.get_u16())
+                    && self
+                        .max_version
+                        .map_or(true, |max| version.get_u16() <= max.get_u16())
+            })
+            .collect();
+
+        if versions.is_empty() {
+            return Err(invalid_input(format!(
+                "no tls versions are allowed by the {:?} policy between {} and {}",
+                policy,
+                describe_version(self.min_version, "TLSv1_2"),
+                describe_version(self.max_version, "TLSv1_3"),
+            )));
+        }
+
+        let cipher_suites = self
+            .cipher_suites
+            .clone()
+            .unwrap_or_else(|| policy.cipher_suites())
+            .into_iter()
+            .map(|suite| {
+                rustls::ALL_CIPHERSUITES
+                    .iter()
+                    .copied()
+                    .find(|supported| supported.suite == suite)
+                    .ok_or_else(|| invalid_input(format!("unsupported cipher suite {:?}", suite)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let cipher_suites: Vec<_> = cipher_suites
+            .into_iter()
+            .filter(|suite| {
+                versions
+                    .iter()
+                    .any(|version| suite.usable_for_version(*version))
+            })
+            .collect();
+
+        if cipher_suites.is_empty() {
+            return Err(invalid_input(format!(
+                "none of the cipher suites can be used with {:?}",
+                versions
+            )));
+        }
+
+        Ok((versions, cipher_suites))
+    }
+}
+
+const SUPPORTED_VERSIONS: [ProtocolVersion; 2] =
+    [ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3];
+
+/// Checks that the versions and cipher suites enabled in `config` leave
+/// at least one way to use this key, since TLS 1.2 ties each cipher
+/// suite to either RSA or ECDSA keys.
+pub(crate) fn check_key(config: &ServerConfig, key: &dyn SigningKey, name: &str) -> io::Result<()> {
+    check_algorithm(config, key.algorithm(), name)
+}
+
+/// Like [`check_key`], for the certificates obtained over ACME, whose
+/// keys are always ECDSA P-256.
+#[cfg(feature = "acme")]
+pub(crate) fn check_acme_key(config: &ServerConfig) -> io::Result<()> {
+    check_algorithm(config, SignatureAlgorithm::ECDSA, "acme cert")
+}
+
+fn check_algorithm(
+    config: &ServerConfig,
+    algorithm: SignatureAlgorithm,
+    name: &str,
+) -> io::Result<()> {
+    let usable = config.ciphersuites.iter().any(|suite| {
+        suite.usable_for_sigalg(algorithm)
+            && config
+                .versions
+                .iter()
+                .any(|version| suite.usable_for_version(*version))
+    });
+
+    if usable {
+        Ok(())
+    } else {
+        Err(invalid_input(format!(
+            "{}: the allowed tls versions and cipher suites cannot be used with its {:?} key; \
+             allow TLSv1_3 or a cipher suite for {:?} keys",
+            name, algorithm, algorithm
+        )))
+    }
+}
+
+fn describe_version(version: Option<ProtocolVersion>, default: &str) -> String {
+    version.map_or_else(|| default.to_string(), |version| format!("{:?}", version))
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificates::KeySource;
+    use rustls::{sign, NoClientAuth};
+
+    fn options(
+        policy: Option<TlsPolicy>,
+        min_version: Option<ProtocolVersion>,
+        max_version: Option<ProtocolVersion>,
+    ) -> ProtocolOptions {
+        ProtocolOptions {
+            policy,
+            min_version,
+            max_version,
+            cipher_suites: None,
+        }
+    }
+
+    fn suites(cipher_suites: &[&SupportedCipherSuite]) -> Vec<CipherSuite> {
+        cipher_suites.iter().map(|suite| suite.suite).collect()
+    }
+
+    fn error(options: &ProtocolOptions) -> String {
+        let error = options.resolve().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        error.to_string()
+    }
+
+    fn key(pem: &[u8]) -> Box<dyn SigningKey> {
+        let key = KeySource::Pem(pem.to_vec()).load().unwrap();
+        sign::any_supported_type(&key).unwrap()
+    }
+
+    fn server_config(options: &ProtocolOptions) -> ServerConfig {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        options.apply(&mut config).unwrap();
+        config
+    }
+
+    #[test]
+    fn defaults_to_intermediate() {
+        let options = ProtocolOptions::default();
+        assert!(options.is_default());
+
+        let (versions, cipher_suites) = options.resolve().unwrap();
+        assert_eq!(versions, TlsPolicy::Intermediate.versions());
+        assert_eq!(
+            suites(&cipher_suites),
+            TlsPolicy::Intermediate.cipher_suites()
+        );
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        let before = suites(&config.ciphersuites);
+        options.apply(&mut config).unwrap();
+        assert_eq!(suites(&config.ciphersuites), before);
+    }
+
+    #[test]
+    fn modern_is_tls13_only() {
+        let (versions, cipher_suites) = options(Some(TlsPolicy::Modern), None, None)
+            .resolve()
+            .unwrap();
+        assert_eq!(versions, vec![ProtocolVersion::TLSv1_3]);
+        assert_eq!(suites(&cipher_suites), TlsPolicy::Modern.cipher_suites());
+    }
+
+    #[test]
+    fn min_and_max_versions_filter_the_policy() {
+        let (versions, cipher_suites) = options(None, Some(ProtocolVersion::TLSv1_3), None)
+            .resolve()
+            .unwrap();
+        assert_eq!(versions, vec![ProtocolVersion::TLSv1_3]);
+        assert_eq!(suites(&cipher_suites), TlsPolicy::Modern.cipher_suites());
+
+        let (versions, cipher_suites) = options(None, None, Some(ProtocolVersion::TLSv1_2))
+            .resolve()
+            .unwrap();
+        assert_eq!(versions, vec![ProtocolVersion::TLSv1_2]);
+        assert_eq!(
+            suites(&cipher_suites),
+            TlsPolicy::Intermediate.cipher_suites()[3..]
+        );
+    }
+
+    #[test]
+    fn versions_that_empty_the_policy() {
+        let message = error(&options(
+            None,
+            Some(ProtocolVersion::TLSv1_3),
+            Some(ProtocolVersion::TLSv1_2),
+        ));
+        assert_eq!(
+            message,
+            "no tls versions are allowed by the Intermediate policy between TLSv1_3 and TLSv1_2"
+        );
+
+        let message = error(&options(
+            Some(TlsPolicy::Modern),
+            None,
+            Some(ProtocolVersion::TLSv1_2),
+        ));
+        assert_eq!(
+            message,
+            "no tls versions are allowed by the Modern policy between TLSv1_2 and TLSv1_2"
+        );
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        let message = error(&options(None, Some(ProtocolVersion::TLSv1_1), None));
+        assert!(
+            message.starts_with("unsupported tls version TLSv1_1"),
+            "{}",
+            message
+        );
+
+        let message = error(&options(None, None, Some(ProtocolVersion::SSLv3)));
+        assert!(
+            message.starts_with("unsupported tls version SSLv3"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn cipher_suites() {
+        let mut options = options(None, None, None);
+        options.cipher_suites = Some(vec![
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+        ]);
+        let (_, cipher_suites) = options.resolve().unwrap();
+        assert_eq!(
+            suites(&cipher_suites),
+            options.cipher_suites.clone().unwrap()
+        );
+
+        options.cipher_suites = Some(vec![CipherSuite::TLS_RSA_WITH_AES_128_CBC_SHA]);
+        assert_eq!(
+            error(&options),
+            "unsupported cipher suite TLS_RSA_WITH_AES_128_CBC_SHA"
+        );
+
+        options.cipher_suites = Some(vec![CipherSuite::TLS13_AES_128_GCM_SHA256]);
+        options.max_version = Some(ProtocolVersion::TLSv1_2);
+        assert_eq!(
+            error(&options),
+            "none of the cipher suites can be used with [TLSv1_2]"
+        );
+    }
+
+    #[test]
+    fn check_key_needs_a_usable_cipher_suite() {
+        let rsa = key(include_bytes!("../tests/fixtures/rsa-pkcs8.key"));
+        let ec = key(include_bytes!("../tests/fixtures/ec-pkcs8.key"));
+
+        let mut ecdsa_only = options(None, None, Some(ProtocolVersion::TLSv1_2));
+        ecdsa_only.cipher_suites = Some(vec![
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+        ]);
+        let config = server_config(&ecdsa_only);
+        check_key(&config, &*ec, "ec").unwrap();
+        let error = check_key(&config, &*rsa, "rsa").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().starts_with("rsa: "), "{}", error);
+
+        ecdsa_only.max_version = None;
+        let config = server_config(&ecdsa_only);
+        check_key(&config, &*ec, "ec").unwrap();
+        check_key(&config, &*rsa, "rsa").unwrap();
+
+        let config = server_config(&options(Some(TlsPolicy::Modern), None, None));
+        check_key(&config, &*rsa, "rsa").unwrap();
+        #[cfg(feature = "acme")]
+        check_acme_key(&config).unwrap();
+    }
+}
@@ -1,3 +1,5 @@
+use crate::connection_observer::ByteCounts;
+
 use async_dup::{Arc, Mutex};
 use async_rustls::server::TlsStream;
 use async_std::io::{Read, Result, Write};
@@ -6,11 +8,11 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 
 #[derive(Clone)]
-pub(crate) struct TlsStreamWrapper(Arc<Mutex<TlsStream<TcpStream>>>);
+pub(crate) struct TlsStreamWrapper(Arc<Mutex<TlsStream<TcpStream>>>, ByteCounts);
 
 impl TlsStreamWrapper {
-    pub(crate) fn new(stream: TlsStream<TcpStream>) -> Self {
-        Self(Arc::new(Mutex::new(stream)))
+    pub(crate) fn new(stream: TlsStream<TcpStream>, byte_counts: ByteCounts) -> Self {
+        Self(Arc::new(Mutex::new(stream)), byte_counts)
     }
 }
 
@@ -20,13 +22,21 @@ impl Read for TlsStreamWrapper {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize>> {
-        Pin::new(&mut &*self.0).poll_read(cx, buf)
+        let poll = Pin::new(&mut &*self.0).poll_read(cx, buf);
+        if let Poll::Ready(Ok(bytes)) = poll {
+            self.1.add_read(bytes);
+        }
+        poll
     }
 }
 
 impl Write for TlsStreamWrapper {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
-        Pin::new(&mut &*self.0).poll_write(cx, buf)
+        let poll = Pin::new(&mut &*self.0).poll_write(cx, buf);
+        if let Poll::Ready(Ok(bytes)) = poll {
+            self.1.add_written(bytes);
+        }
+        poll
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {